@@ -0,0 +1,2 @@
+pub mod offsets_command;
+pub mod poison_command;
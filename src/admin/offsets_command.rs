@@ -0,0 +1,59 @@
+use crate::kafka::start_position::{self, StartPosition};
+use crate::setting::settings::KafkaConfig;
+use anyhow::{Error, anyhow};
+
+const USAGE: &str = "Usage: guelderose offsets seek <--timestamp <ms> | <partition>:<offset>...>";
+
+/// Handles `guelderose offsets ...` commands, which move the consumer group of the input topic once.
+/// The service must be stopped while the command runs.
+pub async fn run(args: &[String], kafka_config: &KafkaConfig) -> Result<(), Error> {
+    let command = args.first().map(String::as_str).ok_or_else(|| anyhow!(USAGE))?;
+
+    match command {
+        "seek" => {
+            let start_position = parse_start_position(&args[1..])?;
+            let offsets = start_position::apply(kafka_config, start_position).await?;
+
+            for element in offsets.elements() {
+                println!(
+                    "Consumer group [{}] is moved to offset {:?} of topic [{}], partition {}",
+                    kafka_config.group_id,
+                    element.offset(),
+                    element.topic(),
+                    element.partition()
+                );
+            }
+        }
+        _ => return Err(anyhow!(USAGE)),
+    }
+
+    Ok(())
+}
+
+fn parse_start_position(args: &[String]) -> Result<StartPosition, Error> {
+    match args {
+        [flag, timestamp_ms] if flag == "--timestamp" => {
+            let timestamp_ms = timestamp_ms
+                .parse::<i64>()
+                .map_err(|err| anyhow!("Invalid timestamp [{timestamp_ms}]: {err}"))?;
+
+            Ok(StartPosition::Timestamp(timestamp_ms))
+        }
+        [] => Err(anyhow!(USAGE)),
+        partition_offsets => partition_offsets
+            .iter()
+            .map(|arg| {
+                let (partition, offset) = arg.split_once(':').ok_or_else(|| anyhow!(USAGE))?;
+                let partition = partition
+                    .parse::<i32>()
+                    .map_err(|err| anyhow!("Invalid partition [{partition}]: {err}"))?;
+                let offset = offset
+                    .parse::<i64>()
+                    .map_err(|err| anyhow!("Invalid offset [{offset}]: {err}"))?;
+
+                Ok((partition, offset))
+            })
+            .collect::<Result<Vec<_>, Error>>()
+            .map(StartPosition::Offsets),
+    }
+}
@@ -0,0 +1,76 @@
+use crate::models::input_message::InputMessage;
+use crate::models::output_message::OutputMessage;
+use crate::setting::settings::RedisQueues;
+use crate::storage::poison_queue::PoisonQueue;
+use crate::storage::queue::Queue;
+use anyhow::{Error, anyhow};
+
+const USAGE: &str = "Usage: guelderose poison <list [start] [count] | inspect <index> | requeue <index> | purge>";
+
+/// Handles `guelderose poison ...` commands for the poison queue.
+/// Entries are requeued to the outbox queue as results, and to any other queue as tasks.
+pub async fn run(
+    args: &[String],
+    poison_queue: &PoisonQueue,
+    queues: &RedisQueues,
+    inbox_queue: &dyn Queue<InputMessage>,
+    outbox_queue: &dyn Queue<OutputMessage>,
+) -> Result<(), Error> {
+    let command = args.first().map(String::as_str).ok_or_else(|| anyhow!(USAGE))?;
+
+    match command {
+        "list" => {
+            let start = parse_arg(args.get(1), 0)?;
+            let count = parse_arg(args.get(2), 20)?;
+
+            println!("Total entries: {}", poison_queue.len().await?);
+
+            for (offset, entry) in poison_queue.list(start, count).await?.iter().enumerate() {
+                println!(
+                    "[{}] source={} poisoned_at={} error={}",
+                    start + offset as isize,
+                    entry.source_queue,
+                    entry.poisoned_at,
+                    entry.error
+                );
+            }
+        }
+        "inspect" => {
+            let index = parse_arg(args.get(1), 0)?;
+
+            match poison_queue.inspect(index).await? {
+                Some(entry) => println!("{}", serde_json::to_string_pretty(&entry)?),
+                None => println!("No entry at index {index}"),
+            }
+        }
+        "requeue" => {
+            let index = parse_arg(args.get(1), 0)?;
+
+            let entry = poison_queue
+                .inspect(index)
+                .await?
+                .ok_or_else(|| anyhow!("No entry at index {index} in poison queue"))?;
+
+            let entry = if entry.source_queue == queues.outbox {
+                poison_queue.requeue(index, outbox_queue).await?
+            } else {
+                poison_queue.requeue(index, inbox_queue).await?
+            };
+            println!("Entry at index {index} requeued to [{}]", entry.source_queue);
+        }
+        "purge" => {
+            let purged = poison_queue.purge().await?;
+            println!("Purged {purged} entries");
+        }
+        _ => return Err(anyhow!(USAGE)),
+    }
+
+    Ok(())
+}
+
+fn parse_arg(arg: Option<&String>, default: isize) -> Result<isize, Error> {
+    arg.map_or(Ok(default), |arg| {
+        arg.parse::<isize>()
+            .map_err(|err| anyhow!("Invalid number [{arg}]: {err}"))
+    })
+}
@@ -1,63 +1,599 @@
-use crate::kafka::kafka_producer::AnyKafkaProducer;
-use crate::models::input_message::InputMessage;
+use crate::kafka::producer::MessageProducer;
+use crate::models::output_message::{OutputMessage, TaskStatus};
+use crate::models::queue_envelope::QueueEnvelope;
 use crate::setting::settings::Settings;
-use crate::storage::redis_queue::RedisQueue;
+use crate::storage::object_store::{ObjectStore, build_task_key, transfer};
+use crate::storage::queue::{Delivery, Queue};
+use crate::storage::task_tracker::TaskTracker;
 use anyhow::Error;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+/// Maximum number of deferred deliveries skipped in one read of the queue.
+const MAX_DEFERRED_PER_READ: usize = 100;
 
 pub struct OutboxDaemon {
-    redis_queue: Arc<RedisQueue>,
-    producer: AnyKafkaProducer,
+    queue: Arc<dyn Queue<OutputMessage>>,
+    task_tracker: Arc<dyn TaskTracker>,
+    outer_storage: Arc<dyn ObjectStore>,
+    inner_storage: Arc<dyn ObjectStore>,
+    producer: Arc<dyn MessageProducer>,
     config: Arc<Settings>,
 }
 
 impl OutboxDaemon {
-    pub fn new(redis_queue: Arc<RedisQueue>, config: Arc<Settings>, producer: AnyKafkaProducer) -> Self {
+    pub fn new(
+        queue: Arc<dyn Queue<OutputMessage>>,
+        task_tracker: Arc<dyn TaskTracker>,
+        outer_storage: Arc<dyn ObjectStore>,
+        inner_storage: Arc<dyn ObjectStore>,
+        config: Arc<Settings>,
+        producer: Arc<dyn MessageProducer>,
+    ) -> Self {
         OutboxDaemon {
-            redis_queue,
+            queue,
+            task_tracker,
+            outer_storage,
+            inner_storage,
             producer,
             config,
         }
     }
 
-    async fn process_message(&self, message: InputMessage) -> Result<(), Error> {
+    /// Copies the recognition result from inner to outer storage.
+    /// Returns the outer storage key, or `None` if the result does not exist.
+    async fn transfer_result(&self, message_id: &str, result_path: &str) -> Result<Option<String>, Error> {
+        let max_attempts = self.config.s3.transfer_max_attempts.max(1);
+        let retry_delay = Duration::from_millis(self.config.s3.transfer_retry_delay_ms);
+        let outer_result_path = build_task_key(message_id, result_path);
+
+        let mut attempt = 1;
+
+        loop {
+            let result =
+                transfer(self.inner_storage.as_ref(), result_path, self.outer_storage.as_ref(), &outer_result_path)
+                    .await;
+
+            match result {
+                Ok(true) => return Ok(Some(outer_result_path)),
+                Ok(false) => return Ok(None),
+                Err(err) if attempt < max_attempts => {
+                    tracing::warn!("Failed to transfer result (attempt {attempt}/{max_attempts}): {err}");
+
+                    attempt += 1;
+                    tokio::time::sleep(retry_delay).await;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Transfers the result of a successful task to outer storage, and returns the envelope to publish.
+    /// A missing result turns the task into a failed one.
+    async fn prepare_message(&self, delivery: &Delivery<OutputMessage>) -> Result<QueueEnvelope<OutputMessage>, Error> {
+        let mut envelope = delivery.envelope.clone();
+        let message = &mut envelope.message;
+
+        if message.status == TaskStatus::Success
+            && let Some(result_path) = message.result_path.clone()
+        {
+            match self.transfer_result(&message.id, &result_path).await {
+                Ok(Some(outer_result_path)) => message.result_path = Some(outer_result_path),
+                Ok(None) => {
+                    tracing::warn!("Result [{result_path}] not found in inner storage");
+
+                    message.status = TaskStatus::Failed;
+                    message.result_path = None;
+                    message.error_code = Some("RESULT_NOT_FOUND".to_string());
+                    message.error_message = Some(format!("Result not found: {result_path}"));
+                }
+                Err(err) => return Err(err.context("Failed to transfer result")),
+            }
+        }
+
+        message.published_at = Some(SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis());
+
+        Ok(envelope)
+    }
+
+    async fn process_message(&self, delivery: Delivery<OutputMessage>) -> Result<(), Error> {
         let queue_key = self.config.redis.queues.outbox.as_str();
 
-        // TODO: Добавить выгрузку результата inner_storage (S3), и загрузку в outer_storage (S3). Выгрузка и загрузка происходят перед отправкой message в Kafka.
-        let result = self.producer.send(message.clone()).await;
+        let span = delivery.envelope.span();
+
+        let envelope = match self.prepare_message(&delivery).instrument(span.clone()).await {
+            Ok(envelope) => envelope,
+            Err(err) => return self.retry_or_dead_letter(queue_key, vec![delivery], err).await,
+        };
+
+        let result = self.producer.send(&envelope).instrument(span).await;
 
         match result {
-            Ok(()) => Ok(()),
+            Ok(()) => self.queue.ack(queue_key, &delivery).await,
+            Err(err) => {
+                let err = err.context("Failed to publish message");
+                self.retry_or_dead_letter(queue_key, vec![delivery], err).await
+            }
+        }
+    }
+
+    /// Publishes the messages in one Kafka transaction.
+    /// Deliveries are acknowledged only after the transaction is committed, and returned to the queue otherwise.
+    async fn process_transaction(&self, deliveries: Vec<Delivery<OutputMessage>>) -> Result<(), Error> {
+        let queue_key = self.config.redis.queues.outbox.as_str();
+
+        let mut prepared_deliveries = Vec::with_capacity(deliveries.len());
+        let mut envelopes = Vec::with_capacity(deliveries.len());
+
+        for delivery in deliveries {
+            let span = delivery.envelope.span();
+
+            match self.prepare_message(&delivery).instrument(span).await {
+                Ok(envelope) => {
+                    prepared_deliveries.push(delivery);
+                    envelopes.push(envelope);
+                }
+                Err(err) => self.retry_or_dead_letter(queue_key, vec![delivery], err).await?,
+            }
+        }
+
+        if envelopes.is_empty() {
+            return Ok(());
+        }
+
+        match self.producer.send_transaction(&envelopes).await {
+            Ok(()) => {
+                for delivery in &prepared_deliveries {
+                    self.queue.ack(queue_key, delivery).await?;
+                }
+
+                tracing::info!("Transaction of {} messages committed", envelopes.len());
+                Ok(())
+            }
             Err(err) => {
-                log::error!(
-                    "Failed to process message: {err}. Message will be returned to the queue: [{queue_key}]. MessageId: {}",
-                    message.id
-                );
+                let err = err.context("Failed to publish messages in a transaction");
+                self.retry_or_dead_letter(queue_key, prepared_deliveries, err).await
+            }
+        }
+    }
+
+    /// Returns the deliveries to the queue, postponed by a backoff delay,
+    /// or moves them to the dead-letter queue once the maximum number of attempts is reached.
+    /// The deliveries are returned right away, so another reader never takes over a delivery held during the delay.
+    async fn retry_or_dead_letter(
+        &self,
+        queue_key: &str,
+        deliveries: Vec<Delivery<OutputMessage>>,
+        err: Error,
+    ) -> Result<(), Error> {
+        let retry_config = &self.config.kafka.publish_retry;
+        let dead_letter_key = self.config.redis.queues.dead_letter.as_str();
+
+        for mut delivery in deliveries {
+            delivery.envelope.attempts += 1;
+            let attempts = delivery.envelope.attempts;
+            let span = delivery.envelope.span();
+
+            if attempts >= retry_config.max_attempts {
+                span.in_scope(|| {
+                    tracing::error!(
+                        "{err:#}. Attempts exhausted ({attempts}), message will be moved to the queue: [{dead_letter_key}]"
+                    )
+                });
 
-                self.redis_queue.push(queue_key, message).await
+                self.queue.push(dead_letter_key, delivery.envelope.clone()).await?;
+                self.queue.ack(queue_key, &delivery).await?;
+                continue;
             }
+
+            let delay = retry_config.backoff.delay(attempts);
+            delivery.envelope.defer(delay);
+
+            span.in_scope(|| {
+                tracing::error!(
+                    "{err:#}. Attempt {attempts}/{}, message will be published again in {delay:?}",
+                    retry_config.max_attempts
+                )
+            });
+
+            self.queue.nack(queue_key, delivery).await?;
         }
+
+        Ok(())
     }
 
-    async fn process_queue(&self) -> Result<(), Error> {
-        let queue_key = self.config.redis.queues.inbox.as_str();
-        let duration = Duration::from_millis(100);
+    /// Reads a batch of deliveries due for publishing, and publishes them.
+    /// Returns `false` if there was nothing to publish.
+    async fn process_batch(&self) -> Result<bool, Error> {
+        let queue_key = self.config.redis.queues.outbox.as_str();
+        let batch_size = if self.producer.is_transactional() {
+            self.config.kafka.producer.transaction_batch_size.max(1)
+        } else {
+            1
+        };
 
-        loop {
-            let result = self.redis_queue.pop(queue_key).await?;
+        let mut deliveries = Vec::with_capacity(batch_size);
+        let mut deferred_deliveries = Vec::new();
 
-            match result {
-                Some(message) => {
-                    log::info!("Popped message from queue: [{queue_key}]. MessageId: {}", message.id);
-                    self.process_message(message).await?;
+        while deliveries.len() < batch_size
+            && deferred_deliveries.len() < MAX_DEFERRED_PER_READ
+            && let Some(delivery) = self.queue.pop(queue_key).await?
+        {
+            self.task_tracker
+                .complete(std::slice::from_ref(&delivery.envelope.message.id))
+                .await?;
+
+            if delivery.envelope.is_deferred() {
+                deferred_deliveries.push(delivery);
+                continue;
+            }
+
+            tracing::info!(id = delivery.envelope.message.id, "Popped message from queue: [{queue_key}]");
+            deliveries.push(delivery);
+        }
+
+        // Deferred deliveries are returned once the batch is read, so they are not read again in the same batch.
+        for delivery in deferred_deliveries {
+            self.queue.nack(queue_key, delivery).await?;
+        }
+
+        if deliveries.is_empty() {
+            return Ok(false);
+        }
+
+        if self.producer.is_transactional() {
+            self.process_transaction(deliveries).await?;
+        } else {
+            for delivery in deliveries {
+                self.process_message(delivery).await?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    async fn process_queue(&self, token: &CancellationToken) -> Result<(), Error> {
+        let duration = Duration::from_millis(self.config.redis.read_delay_ms);
+
+        while !token.is_cancelled() {
+            if !self.process_batch().await? {
+                tokio::select! {
+                    _ = tokio::time::sleep(duration) => {}
+                    _ = token.cancelled() => {}
                 }
-                None => tokio::time::sleep(duration).await,
             }
         }
+
+        Ok(())
+    }
+
+    /// Processes the outbox queue until the token is cancelled.
+    /// The message (or transaction) in progress is finished, and the producer is flushed before returning.
+    pub async fn start(self, token: CancellationToken) -> Result<(), Error> {
+        let result = self.process_queue(&token).await;
+
+        if let Err(err) = self.producer.flush(self.config.shutdown.drain_timeout()) {
+            tracing::error!("Failed to flush Kafka producer: {err}");
+        }
+
+        tracing::info!("Outbox daemon stopped");
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::input_message::{InputMessage, PredictType};
+    use crate::models::message_header::MessageHeader;
+    use crate::test_support::{
+        DEAD_LETTER, INBOX, OUTBOX, OUTPUT_TOPIC, TestService, output_message, record, settings, task_payload,
+    };
+
+    fn delivery(id: &str, status: TaskStatus, attempts: u32) -> Delivery<OutputMessage> {
+        let mut envelope = QueueEnvelope::new(output_message(id, status));
+        envelope.attempts = attempts;
+
+        Delivery {
+            envelope,
+            receipt: None,
+        }
+    }
+
+    fn transactional_settings() -> Settings {
+        let mut settings = settings();
+        settings.kafka.producer.transactional_id = Some("guelderose-test".to_string());
+        settings
+    }
+
+    #[tokio::test]
+    async fn transfers_result_of_successful_task_to_outer_storage() {
+        let service = TestService::new(settings());
+        service
+            .inner_storage
+            .put_object("results/1.json", b"result".to_vec())
+            .await
+            .unwrap();
+
+        let envelope = service
+            .daemon()
+            .prepare_message(&delivery("1", TaskStatus::Success, 0))
+            .await
+            .unwrap();
+
+        assert_eq!(envelope.message.status, TaskStatus::Success);
+        assert_eq!(envelope.message.result_path.as_deref(), Some("1/1.json"));
+        assert!(envelope.message.published_at.is_some());
+        assert_eq!(service.outer_storage.get_object("1/1.json").await.unwrap(), Some(b"result".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn fails_successful_task_with_missing_result() {
+        let service = TestService::new(settings());
+
+        let envelope = service
+            .daemon()
+            .prepare_message(&delivery("2", TaskStatus::Success, 0))
+            .await
+            .unwrap();
+
+        assert_eq!(envelope.message.status, TaskStatus::Failed);
+        assert_eq!(envelope.message.result_path, None);
+        assert_eq!(envelope.message.error_code.as_deref(), Some("RESULT_NOT_FOUND"));
+    }
+
+    #[tokio::test]
+    async fn publishes_message_and_removes_it_from_outbox() {
+        let service = TestService::new(settings());
+        service
+            .outbox_queue
+            .push(OUTBOX, delivery("3", TaskStatus::Failed, 0).envelope)
+            .await
+            .unwrap();
+
+        assert!(service.daemon().process_batch().await.unwrap());
+
+        let published = service.producer.published_messages();
+        assert_eq!(published.len(), 1);
+        assert_eq!(published[0].id, "3");
+        assert!(published[0].published_at.is_some());
+        assert_eq!(service.outbox_queue.len(OUTBOX).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn returns_message_to_outbox_after_failed_publish() {
+        let service = TestService::new(settings());
+        service.producer.set_failing(true);
+
+        service
+            .daemon()
+            .process_message(delivery("4", TaskStatus::Failed, 0))
+            .await
+            .unwrap();
+
+        let returned = service.outbox_queue.pop(OUTBOX).await.unwrap().unwrap();
+        assert_eq!(returned.envelope.message.id, "4");
+        assert_eq!(returned.envelope.attempts, 1);
+        assert!(returned.envelope.is_deferred());
+        assert_eq!(service.outbox_queue.len(DEAD_LETTER).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn moves_message_to_dead_letter_once_attempts_are_exhausted() {
+        let service = TestService::new(settings());
+        service.producer.set_failing(true);
+
+        service
+            .daemon()
+            .process_message(delivery("5", TaskStatus::Failed, 2))
+            .await
+            .unwrap();
+
+        assert_eq!(service.outbox_queue.len(OUTBOX).await.unwrap(), 0);
+
+        let dead_letter = service.outbox_queue.pop(DEAD_LETTER).await.unwrap().unwrap();
+        assert_eq!(dead_letter.envelope.message.id, "5");
+        assert_eq!(dead_letter.envelope.attempts, 3);
+    }
+
+    #[tokio::test]
+    async fn publishes_deferred_message_only_after_its_retry_time() {
+        let service = TestService::new(settings());
+        let daemon = service.daemon();
+        service
+            .outbox_queue
+            .push(OUTBOX, delivery("6", TaskStatus::Failed, 0).envelope)
+            .await
+            .unwrap();
+
+        service.producer.set_failing(true);
+        assert!(daemon.process_batch().await.unwrap());
+
+        service.producer.set_failing(false);
+        assert!(!daemon.process_batch().await.unwrap());
+        assert!(service.producer.published_messages().is_empty());
+
+        let mut returned = service.outbox_queue.pop(OUTBOX).await.unwrap().unwrap();
+        assert_eq!(returned.envelope.attempts, 1);
+
+        returned.envelope.not_before = Some(0);
+        service.outbox_queue.push(OUTBOX, returned.envelope).await.unwrap();
+
+        assert!(daemon.process_batch().await.unwrap());
+        assert_eq!(service.producer.published_messages()[0].id, "6");
+        assert_eq!(service.outbox_queue.len(OUTBOX).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn completes_popped_tasks_and_keeps_unpublished_messages_on_shutdown() {
+        let service = TestService::new(settings());
+        let daemon = service.daemon();
+        let task_ids = vec!["7".to_string()];
+
+        service.task_tracker.track(&task_ids).await.unwrap();
+        service
+            .outbox_queue
+            .push(OUTBOX, delivery("7", TaskStatus::Failed, 0).envelope)
+            .await
+            .unwrap();
+
+        service.producer.set_failing(true);
+        assert!(daemon.process_batch().await.unwrap());
+
+        let token = CancellationToken::new();
+        token.cancel();
+        daemon.start(token).await.unwrap();
+
+        assert_eq!(service.task_tracker.pending(&task_ids).await.unwrap(), 0);
+
+        let returned = service.outbox_queue.pop(OUTBOX).await.unwrap().unwrap();
+        assert_eq!(returned.envelope.message.id, "7");
+        assert_eq!(returned.envelope.attempts, 1);
     }
 
-    pub async fn start(self) -> Result<(), Error> {
-        self.process_queue().await
+    #[tokio::test]
+    async fn publishes_batch_in_one_transaction() {
+        let service = TestService::new(transactional_settings());
+
+        for id in ["8", "9"] {
+            service
+                .outbox_queue
+                .push(OUTBOX, delivery(id, TaskStatus::Failed, 0).envelope)
+                .await
+                .unwrap();
+        }
+
+        assert!(service.daemon().process_batch().await.unwrap());
+
+        assert_eq!(service.producer.committed_transactions(), 1);
+
+        let published_ids: Vec<String> = service
+            .producer
+            .published_messages()
+            .into_iter()
+            .map(|message| message.id)
+            .collect();
+        assert_eq!(published_ids, ["8", "9"]);
+        assert_eq!(service.outbox_queue.len(OUTBOX).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn returns_all_messages_of_failed_transaction_to_outbox() {
+        let service = TestService::new(transactional_settings());
+        service.producer.set_failing(true);
+
+        for id in ["10", "11"] {
+            service
+                .outbox_queue
+                .push(OUTBOX, delivery(id, TaskStatus::Failed, 0).envelope)
+                .await
+                .unwrap();
+        }
+
+        assert!(service.daemon().process_batch().await.unwrap());
+
+        assert_eq!(service.producer.committed_transactions(), 0);
+        assert!(service.producer.records().is_empty());
+
+        for id in ["10", "11"] {
+            let returned = service.outbox_queue.pop(OUTBOX).await.unwrap().unwrap();
+            assert_eq!(returned.envelope.message.id, id);
+            assert_eq!(returned.envelope.attempts, 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_outbox_and_leaves_inbox_to_workers() {
+        let service = TestService::new(settings());
+
+        let task = InputMessage {
+            id: "12".to_string(),
+            image_path: "12/12.png".to_string(),
+            result_path: "results/12.json".to_string(),
+            predict_type: PredictType::TypeOne,
+        };
+        service.inbox_queue.push(INBOX, QueueEnvelope::new(task)).await.unwrap();
+        service
+            .outbox_queue
+            .push(OUTBOX, delivery("13", TaskStatus::Failed, 0).envelope)
+            .await
+            .unwrap();
+
+        let daemon = service.daemon();
+        assert!(daemon.process_batch().await.unwrap());
+        assert!(!daemon.process_batch().await.unwrap());
+
+        assert_eq!(service.producer.published_messages()[0].id, "13");
+
+        let task = service.inbox_queue.pop(INBOX).await.unwrap().unwrap();
+        assert_eq!(task.envelope.message.id, "12");
+        assert_eq!(task.envelope.attempts, 0);
+    }
+
+    /// Consumed record, inbox task, result of the worker in the outbox, published result: the whole flow without brokers.
+    #[tokio::test]
+    async fn publishes_result_of_consumed_task() {
+        let service = TestService::new(settings());
+        let processor = service.processor();
+        let daemon = service.daemon();
+        service
+            .outer_storage
+            .put_object("images/1.png", b"image".to_vec())
+            .await
+            .unwrap();
+
+        let headers = vec![MessageHeader {
+            key: "correlation-id".to_string(),
+            value: Some("abc".to_string()),
+        }];
+        let task_id = processor
+            .process_message(&record(Some(&task_payload("1", "images/1.png"))), headers)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // The recognition worker takes the task, and puts the result to inner storage and the outbox.
+        let task = service.inbox_queue.pop(INBOX).await.unwrap().unwrap();
+        service.inbox_queue.ack(INBOX, &task).await.unwrap();
+
+        let result_path = task.envelope.message.result_path.clone();
+        service
+            .inner_storage
+            .put_object(&result_path, b"result".to_vec())
+            .await
+            .unwrap();
+
+        let mut result = output_message(&task.envelope.message.id, TaskStatus::Success);
+        result.result_path = Some(result_path);
+        let result_envelope = QueueEnvelope::new(result)
+            .with_headers(task.envelope.headers)
+            .with_trace_context(task.envelope.trace_context);
+        service.outbox_queue.push(OUTBOX, result_envelope).await.unwrap();
+
+        assert!(daemon.process_batch().await.unwrap());
+        assert_eq!(
+            service
+                .task_tracker
+                .pending(std::slice::from_ref(&task_id))
+                .await
+                .unwrap(),
+            0
+        );
+
+        let records = service.producer.records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].topic, OUTPUT_TOPIC);
+        assert_eq!(records[0].key.as_deref(), Some(b"1".as_slice()));
+
+        let published = service.producer.published_messages();
+        assert_eq!(published[0].status, TaskStatus::Success);
+        assert_eq!(published[0].result_path.as_deref(), Some("1/1.json"));
+        assert_eq!(service.outer_storage.get_object("1/1.json").await.unwrap(), Some(b"result".to_vec()));
+
+        assert_eq!(service.inbox_queue.len(INBOX).await.unwrap(), 0);
+        assert_eq!(service.outbox_queue.len(OUTBOX).await.unwrap(), 0);
     }
 }
@@ -0,0 +1,98 @@
+use crate::kafka::producer::MessageProducer;
+use crate::storage::object_store::ObjectStore;
+use anyhow::Error;
+use async_trait::async_trait;
+use redis::aio::MultiplexedConnection;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Check of a dependency the service needs to process messages.
+#[async_trait]
+pub trait HealthCheck: Send + Sync {
+    fn name(&self) -> &str;
+
+    async fn check(&self) -> Result<(), Error>;
+}
+
+/// Sends `PING` to Redis.
+pub struct RedisCheck {
+    connection: MultiplexedConnection,
+}
+
+impl RedisCheck {
+    pub fn new(connection: MultiplexedConnection) -> Self {
+        RedisCheck { connection }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for RedisCheck {
+    fn name(&self) -> &str {
+        "redis"
+    }
+
+    async fn check(&self) -> Result<(), Error> {
+        let mut connection = self.connection.clone();
+        redis::cmd("PING").query_async::<String>(&mut connection).await?;
+
+        Ok(())
+    }
+}
+
+/// Fetches metadata of the configured topics from Kafka.
+pub struct KafkaCheck {
+    producer: Arc<dyn MessageProducer>,
+    topics: Vec<String>,
+    timeout: Duration,
+}
+
+impl KafkaCheck {
+    pub fn new(producer: Arc<dyn MessageProducer>, topics: Vec<String>, timeout: Duration) -> Self {
+        KafkaCheck {
+            producer,
+            topics,
+            timeout,
+        }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for KafkaCheck {
+    fn name(&self) -> &str {
+        "kafka"
+    }
+
+    async fn check(&self) -> Result<(), Error> {
+        for topic in &self.topics {
+            self.producer.check_topic(topic, self.timeout).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks the bucket of a file storage (`HeadBucket` for S3).
+pub struct StorageCheck {
+    name: String,
+    storage: Arc<dyn ObjectStore>,
+}
+
+impl StorageCheck {
+    pub fn new(name: &str, storage: Arc<dyn ObjectStore>) -> Self {
+        StorageCheck {
+            name: name.to_string(),
+            storage,
+        }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for StorageCheck {
+    fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    async fn check(&self) -> Result<(), Error> {
+        self.storage.check().await
+    }
+}
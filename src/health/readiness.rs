@@ -0,0 +1,73 @@
+use crate::health::health_check::HealthCheck;
+use futures::future::join_all;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DependencyStatus {
+    Up,
+    Down,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DependencyReport {
+    pub name: String,
+    pub status: DependencyStatus,
+    pub duration_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReadinessReport {
+    pub ready: bool,
+    pub dependencies: Vec<DependencyReport>,
+}
+
+/// Runs the dependency checks concurrently, each limited by the timeout.
+pub struct Readiness {
+    checks: Vec<Arc<dyn HealthCheck>>,
+    timeout: Duration,
+}
+
+impl Readiness {
+    pub fn new(checks: Vec<Arc<dyn HealthCheck>>, timeout: Duration) -> Self {
+        Readiness { checks, timeout }
+    }
+
+    pub async fn report(&self) -> ReadinessReport {
+        let dependencies = join_all(self.checks.iter().map(|check| self.run_check(check.as_ref()))).await;
+        let ready = dependencies
+            .iter()
+            .all(|dependency| dependency.status == DependencyStatus::Up);
+
+        ReadinessReport { ready, dependencies }
+    }
+
+    async fn run_check(&self, check: &dyn HealthCheck) -> DependencyReport {
+        let started_at = Instant::now();
+
+        let error = match tokio::time::timeout(self.timeout, check.check()).await {
+            Ok(Ok(())) => None,
+            Ok(Err(err)) => Some(err.to_string()),
+            Err(_) => Some(format!("Check timed out after {:?}", self.timeout)),
+        };
+
+        if let Some(err) = &error {
+            tracing::warn!("Readiness check [{}] failed: {err}", check.name());
+        }
+
+        DependencyReport {
+            name: check.name().to_string(),
+            status: if error.is_none() {
+                DependencyStatus::Up
+            } else {
+                DependencyStatus::Down
+            },
+            duration_ms: started_at.elapsed().as_millis(),
+            error,
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use crate::health::readiness::Readiness;
+use crate::metrics::registry::METRICS;
+use anyhow::Error;
+use axum::Router;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::{IntoResponse, Json, Response};
+use axum::routing::get;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+
+/// Serves the service endpoints until the token is cancelled.
+pub async fn serve(address: &str, readiness: Arc<Readiness>, token: CancellationToken) -> Result<(), Error> {
+    let router = Router::new()
+        .route("/metrics", get(metrics))
+        .route("/health/live", get(live))
+        .route("/health/ready", get(ready))
+        .with_state(readiness);
+
+    let listener = TcpListener::bind(address).await?;
+    tracing::info!("HTTP server listening on {address}");
+
+    axum::serve(listener, router)
+        .with_graceful_shutdown(token.cancelled_owned())
+        .await?;
+
+    tracing::info!("HTTP server stopped");
+
+    Ok(())
+}
+
+async fn metrics() -> Response {
+    match METRICS.encode() {
+        Ok(body) => ([(CONTENT_TYPE, prometheus::TEXT_FORMAT)], body).into_response(),
+        Err(err) => {
+            tracing::error!("Failed to encode metrics: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// The process is up and serving requests. Dependencies are not checked.
+async fn live() -> Response {
+    Json(serde_json::json!({ "status": "up" })).into_response()
+}
+
+/// Checks the dependencies, and responds with `503 Service Unavailable` if any of them is down.
+async fn ready(State(readiness): State<Arc<Readiness>>) -> Response {
+    let report = readiness.report().await;
+    let status = if report.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (status, Json(report)).into_response()
+}
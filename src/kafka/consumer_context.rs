@@ -0,0 +1,104 @@
+use rdkafka::consumer::{BaseConsumer, CommitMode, Consumer, ConsumerContext, Rebalance};
+use rdkafka::error::KafkaResult;
+use rdkafka::{ClientContext, TopicPartitionList};
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+/// Consumer context, that keeps offsets of processed batches and handles partition rebalances.
+/// Before partitions are revoked, their processed offsets are committed synchronously,
+/// and the revoked partitions are reported to the consumer, so it can drop their messages from the collected batch.
+#[derive(Default)]
+pub struct RebalanceContext {
+    processed_offsets: Mutex<TopicPartitionList>,
+    revoked: Mutex<HashSet<(String, i32)>>,
+}
+
+impl RebalanceContext {
+    /// Stores offsets (next to consume) of a processed batch.
+    pub fn record_processed(&self, offsets: &TopicPartitionList) -> KafkaResult<()> {
+        let mut processed_offsets = self.processed_offsets.lock().expect("poisoned lock");
+
+        for element in offsets.elements() {
+            let (topic, partition, offset) = (element.topic(), element.partition(), element.offset());
+
+            match processed_offsets.find_partition(topic, partition) {
+                Some(_) => processed_offsets.set_partition_offset(topic, partition, offset)?,
+                None => processed_offsets.add_partition_offset(topic, partition, offset)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Offsets of processed batches for the partitions still assigned.
+    pub fn processed_offsets(&self) -> TopicPartitionList {
+        self.processed_offsets.lock().expect("poisoned lock").clone()
+    }
+
+    /// Returns the partitions revoked since the previous call.
+    pub fn take_revoked(&self) -> HashSet<(String, i32)> {
+        std::mem::take(&mut *self.revoked.lock().expect("poisoned lock"))
+    }
+}
+
+impl ClientContext for RebalanceContext {}
+
+impl ConsumerContext for RebalanceContext {
+    fn pre_rebalance(&self, base_consumer: &BaseConsumer<Self>, rebalance: &Rebalance<'_>) {
+        match rebalance {
+            Rebalance::Revoke(partitions) => {
+                tracing::info!("Partitions revoked: {}", describe_partitions(partitions));
+
+                let revoked: HashSet<(String, i32)> = partitions
+                    .elements()
+                    .iter()
+                    .map(|element| (element.topic().to_string(), element.partition()))
+                    .collect();
+
+                let mut processed_offsets = self.processed_offsets.lock().expect("poisoned lock");
+                let mut revoked_offsets = TopicPartitionList::new();
+                let mut retained_offsets = TopicPartitionList::new();
+
+                for element in processed_offsets.elements() {
+                    let target = if revoked.contains(&(element.topic().to_string(), element.partition())) {
+                        &mut revoked_offsets
+                    } else {
+                        &mut retained_offsets
+                    };
+
+                    if let Err(err) =
+                        target.add_partition_offset(element.topic(), element.partition(), element.offset())
+                    {
+                        tracing::error!("Failed to collect offsets of revoked partitions: {err}");
+                    }
+                }
+
+                if revoked_offsets.count() > 0
+                    && let Err(err) = base_consumer.commit(&revoked_offsets, CommitMode::Sync)
+                {
+                    tracing::error!("Failed to commit offsets of revoked partitions: {err}");
+                }
+
+                *processed_offsets = retained_offsets;
+                self.revoked.lock().expect("poisoned lock").extend(revoked);
+            }
+            Rebalance::Assign(_) => {}
+            Rebalance::Error(err) => tracing::error!("Rebalance error: {err}"),
+        }
+    }
+
+    fn post_rebalance(&self, _base_consumer: &BaseConsumer<Self>, rebalance: &Rebalance<'_>) {
+        if let Rebalance::Assign(partitions) = rebalance {
+            tracing::info!("Partitions assigned: {}", describe_partitions(partitions));
+        }
+    }
+}
+
+fn describe_partitions(partitions: &TopicPartitionList) -> String {
+    partitions
+        .elements()
+        .iter()
+        .map(|element| format!("{}[{}]", element.topic(), element.partition()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -1,37 +1,82 @@
+use crate::kafka::consumer_context::RebalanceContext;
+use crate::kafka::message_processor::MessageProcessor;
+use crate::kafka::producer::MessageProducer;
 use crate::models::input_message::InputMessage;
-use crate::setting::settings::{KafkaConfig, RedisConfig};
-use crate::storage::redis_queue::RedisQueue;
+use crate::models::message_header::MessageHeader;
+use crate::models::output_message::OutputMessage;
+use crate::setting::settings::{KafkaCommitMode, KafkaConfig, RedisConfig, ShutdownConfig};
+use crate::storage::object_store::ObjectStore;
+use crate::storage::queue::Queue;
+use crate::storage::task_tracker::TaskTracker;
+use crate::telemetry::propagation::{context_from_headers, continue_trace};
 use anyhow::Error;
 use futures::stream::StreamExt;
-use rdkafka::Message;
 use rdkafka::consumer::{CommitMode, Consumer, StreamConsumer};
-use rdkafka::message::BorrowedMessage;
+use rdkafka::message::{BorrowedMessage, Headers};
+use rdkafka::{Message, Offset, TopicPartitionList};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::time::Duration;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::field::Empty;
+use tracing::{Instrument, Span};
+
+const SEEK_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub struct AnyKafkaConsumer {
-    consumer: StreamConsumer,
+    consumer: StreamConsumer<RebalanceContext>,
     kafka_config: KafkaConfig,
-    redis_config: RedisConfig,
-    redis_queue: Arc<RedisQueue>,
+    processor: MessageProcessor,
+    /// Number of batches failed in a row, for the backoff before the next poll.
+    failed_batches: AtomicU32,
 }
 
 impl AnyKafkaConsumer {
-    pub fn new(redis_queue: Arc<RedisQueue>, redis_config: RedisConfig, kafka_config: KafkaConfig) -> Self {
-        let consumer: StreamConsumer = kafka_config
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        inbox_queue: Arc<dyn Queue<InputMessage>>,
+        outbox_queue: Arc<dyn Queue<OutputMessage>>,
+        task_tracker: Arc<dyn TaskTracker>,
+        outer_storage: Arc<dyn ObjectStore>,
+        inner_storage: Arc<dyn ObjectStore>,
+        producer: Arc<dyn MessageProducer>,
+        redis_config: RedisConfig,
+        kafka_config: KafkaConfig,
+        shutdown_config: ShutdownConfig,
+    ) -> Self {
+        let consumer: StreamConsumer<RebalanceContext> = kafka_config
             .build_kafka_config()
-            .create()
+            .create_with_context(RebalanceContext::default())
             .expect("Consumer creation failed");
 
+        let processor = MessageProcessor::new(
+            inbox_queue,
+            outbox_queue,
+            task_tracker,
+            outer_storage,
+            inner_storage,
+            producer,
+            redis_config,
+            kafka_config.clone(),
+            shutdown_config,
+        );
+
         AnyKafkaConsumer {
             consumer,
             kafka_config,
-            redis_config,
-            redis_queue,
+            processor,
+            failed_batches: AtomicU32::new(0),
         }
     }
 
-    pub async fn consume(&self) -> Result<(), Error> {
+    /// Consumes messages until the token is cancelled.
+    /// The batch in progress is finished, and offsets of processed batches are committed synchronously before returning.
+    /// Messages of an incomplete batch are not committed and will be redelivered.
+    /// Messages of revoked partitions are dropped from the collected batch, the new owner reads them from the committed offset.
+    /// Returns an error if the offsets cannot be kept consistent (e.g. partitions cannot be rewound after a failed message),
+    /// since consuming further would commit past unprocessed messages.
+    pub async fn consume(&self, token: CancellationToken) -> Result<(), Error> {
         let topic = self.kafka_config.topics.input.as_str();
         let batch_size = self.kafka_config.batch_size;
 
@@ -41,63 +86,231 @@ impl AnyKafkaConsumer {
 
         let mut stream = self.consumer.stream();
 
-        while let Some(result) = stream.next().await {
-            match result {
-                Ok(message) => {
-                    batch.push(message);
+        let batch_max_wait = Duration::from_millis(self.kafka_config.batch_max_wait_ms);
+        let mut batch_deadline: Option<Instant> = None;
 
-                    if batch.len() >= batch_size {
-                        if let Err(err) = self.process_batch(&batch).await {
-                            log::error!("Batch processing error: {err}");
-                        }
+        loop {
+            let deadline = batch_deadline;
+            let batch_timer = async move {
+                match deadline {
+                    Some(deadline) => tokio::time::sleep_until(deadline).await,
+                    None => std::future::pending().await,
+                }
+            };
 
-                        batch.clear();
-                    }
+            tokio::select! {
+                _ = token.cancelled() => break,
+                _ = batch_timer => {
+                    tracing::debug!("Batch max wait time expired with {} messages", batch.len());
+
+                    self.flush_batch(&mut batch, &token).await?;
+                    batch_deadline = None;
                 }
-                Err(err) => {
-                    log::error!("Kafka error: {err}");
+                result = stream.next() => {
+                    self.drop_revoked(&mut batch);
+
+                    match result {
+                        Some(Ok(message)) => {
+                            if batch.is_empty() {
+                                batch_deadline = Some(Instant::now() + batch_max_wait);
+                            }
+
+                            batch.push(message);
+
+                            if batch.len() >= batch_size {
+                                self.flush_batch(&mut batch, &token).await?;
+                                batch_deadline = None;
+                            }
+                        }
+                        Some(Err(err)) => tracing::error!("Kafka error: {err}"),
+                        None => {
+                            self.flush_batch(&mut batch, &token).await?;
+                            break;
+                        }
+                    }
                 }
             }
         }
 
         if !batch.is_empty() {
-            if let Err(err) = self.process_batch(&batch).await {
-                log::error!("Final batch processing error: {err}");
-            }
+            tracing::info!("Incomplete batch of {} messages is left uncommitted", batch.len());
+        }
+
+        let processed_offsets = self.consumer.context().processed_offsets();
+        if processed_offsets.count() > 0 {
+            self.consumer.commit(&processed_offsets, CommitMode::Sync)?;
         }
 
+        self.consumer.unsubscribe();
+        tracing::info!("Kafka consumer stopped");
+
         Ok(())
     }
 
-    async fn process_batch(&self, batch: &[BorrowedMessage<'_>]) -> Result<(), Error> {
-        let topic = self.kafka_config.topics.input.as_str();
-        let queue_key = self.redis_config.queues.inbox.as_str();
-        let poll_delay = Duration::from_millis(self.redis_config.poll_delay_ms);
-
-        for message in batch {
-            if let Some(payload) = message.payload() {
-                match serde_json::from_slice::<InputMessage>(payload) {
-                    Ok(message) => {
-                        // TODO: Добавить выгрузку image из outer_storage (S3), и загрузку в inner_storage (S3). Выгрузка и загрузка происходят перед отправкой message в queue.
-                        self.redis_queue.push(queue_key, message.clone()).await?;
-                        log::info!(
-                            "Message consumed from topic: [{topic}] and pushed to queue: [{queue_key}]. MessageId: {}",
-                            message.id
-                        );
-                    }
-                    Err(err) => {
-                        log::warn!("Invalid UTF-8 payload at topic [{topic}]: {err}");
-                    }
+    /// Processes the batch (if not empty) and clears it.
+    /// Messages are processed in order, and processing stops at the first failed message.
+    /// For every partition, the highest contiguous processed offset is committed,
+    /// and partitions with unprocessed messages are rewound to the first of them, so they are redelivered.
+    /// Messages of partitions revoked by a rebalance (which may be handled during any poll) are dropped first.
+    /// On shutdown, the tasks are awaited only for the drain share of the shutdown timeout.
+    /// After a failed batch, the next poll is delayed by an exponential backoff, so a failing backend is not hammered.
+    async fn flush_batch(&self, batch: &mut Vec<BorrowedMessage<'_>>, token: &CancellationToken) -> Result<(), Error> {
+        self.drop_revoked(batch);
+
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let mut task_ids = Vec::with_capacity(batch.len());
+        let mut failure = None;
+
+        for (index, message) in batch.iter().enumerate() {
+            let headers = collect_headers(message);
+            let span = message_span(message);
+            continue_trace(&span, context_from_headers(&headers));
+
+            match self.processor.process_message(message, headers).instrument(span).await {
+                Ok(task_id) => task_ids.extend(task_id),
+                Err(err) => {
+                    failure = Some((index, err));
+                    break;
                 }
             }
         }
 
-        self.redis_queue.check_queue(queue_key, poll_delay).await?;
+        // Pushed tasks are already in the queue, so they are committed even if waiting for them fails.
+        if let Err(err) = self.processor.wait_for_tasks(&task_ids, token).await {
+            tracing::error!("Failed to wait for batch tasks: {err}");
+        }
+
+        let processed_count = failure.as_ref().map_or(batch.len(), |(index, _)| *index);
+        let (processed, unprocessed) = batch.split_at(processed_count);
+
+        if !processed.is_empty() {
+            let mut batch_offsets = TopicPartitionList::new();
+            record_offsets(&mut batch_offsets, processed)?;
+            self.consumer.context().record_processed(&batch_offsets)?;
 
-        for message in batch {
-            self.consumer.commit_message(message, CommitMode::Async)?;
+            let commit_mode = match self.kafka_config.commit_mode {
+                KafkaCommitMode::Sync => CommitMode::Sync,
+                KafkaCommitMode::Async => CommitMode::Async,
+            };
+
+            if let Err(err) = self.consumer.commit(&batch_offsets, commit_mode) {
+                tracing::error!("Failed to commit batch offsets: {err}");
+            }
         }
 
+        let failed = failure.is_some();
+
+        if let Some((_, err)) = failure {
+            tracing::error!(
+                "Batch processing error: {err}. {} of {} messages will be redelivered",
+                unprocessed.len(),
+                batch.len()
+            );
+
+            self.rewind(unprocessed)?;
+        }
+
+        batch.clear();
+
+        if !failed {
+            self.failed_batches.store(0, Ordering::Relaxed);
+            return Ok(());
+        }
+
+        let failed_batches = self.failed_batches.fetch_add(1, Ordering::Relaxed) + 1;
+        let delay = self.kafka_config.consume_backoff.delay(failed_batches);
+        tracing::info!("Consuming will be resumed in {delay:?} ({failed_batches} batches failed in a row)");
+
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = token.cancelled() => {}
+        }
+
+        Ok(())
+    }
+
+    /// Drops messages of the partitions revoked since the batch was collected.
+    fn drop_revoked(&self, batch: &mut Vec<BorrowedMessage<'_>>) {
+        let revoked = self.consumer.context().take_revoked();
+        if revoked.is_empty() {
+            return;
+        }
+
+        let batch_len = batch.len();
+        batch.retain(|message| !revoked.contains(&(message.topic().to_string(), message.partition())));
+
+        if batch.len() < batch_len {
+            tracing::info!("{} messages of revoked partitions are dropped from the batch", batch_len - batch.len());
+        }
+    }
+
+    /// Seeks every partition of the messages back to the first of them.
+    fn rewind(&self, messages: &[BorrowedMessage<'_>]) -> Result<(), Error> {
+        let mut positions = TopicPartitionList::new();
+
+        for message in messages {
+            if positions.find_partition(message.topic(), message.partition()).is_none() {
+                positions.add_partition_offset(
+                    message.topic(),
+                    message.partition(),
+                    Offset::Offset(message.offset()),
+                )?;
+            }
+        }
+
+        self.consumer.seek_partitions(positions, SEEK_TIMEOUT)?;
+
         Ok(())
     }
 }
+
+/// Copies headers of the record to propagate them to the output record. Headers with non UTF-8 values are skipped.
+fn collect_headers(message: &impl Message) -> Vec<MessageHeader> {
+    let Some(headers) = message.headers() else {
+        return Vec::new();
+    };
+
+    headers
+        .iter()
+        .filter_map(|header| match header.value.map(std::str::from_utf8).transpose() {
+            Ok(value) => Some(MessageHeader {
+                key: header.key.to_string(),
+                value: value.map(str::to_string),
+            }),
+            Err(_) => {
+                tracing::warn!("Header [{}] has a non UTF-8 value and will not be propagated", header.key);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Stores the next offset to consume for every partition of the batch.
+fn record_offsets(offsets: &mut TopicPartitionList, batch: &[BorrowedMessage<'_>]) -> Result<(), Error> {
+    for message in batch {
+        let offset = Offset::Offset(message.offset() + 1);
+
+        match offsets.find_partition(message.topic(), message.partition()) {
+            Some(_) => offsets.set_partition_offset(message.topic(), message.partition(), offset)?,
+            None => offsets.add_partition_offset(message.topic(), message.partition(), offset)?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Span of a consumed record. Id and predict type are recorded once the payload is parsed, trace id once the parent is set.
+fn message_span(message: &impl Message) -> Span {
+    tracing::info_span!(
+        "message",
+        topic = message.topic(),
+        partition = message.partition(),
+        offset = message.offset(),
+        id = Empty,
+        predict_type = Empty,
+        trace_id = Empty,
+    )
+}
@@ -1,38 +1,266 @@
-use crate::models::input_message::InputMessage;
-use crate::setting::settings::KafkaConfig;
-use anyhow::Error;
-use rdkafka::ClientConfig;
-use rdkafka::producer::{FutureProducer, FutureRecord};
-use std::time::Duration;
+use crate::kafka::producer::MessageProducer;
+use crate::metrics::registry::METRICS;
+use crate::models::output_message::OutputMessage;
+use crate::models::queue_envelope::QueueEnvelope;
+use crate::setting::settings::{KafkaConfig, MessageKeyField};
+use crate::telemetry::propagation::{TRACE_CONTEXT_HEADERS, context_to_map};
+use anyhow::{Error, bail};
+use async_trait::async_trait;
+use rdkafka::message::{Header, OwnedHeaders};
+use rdkafka::producer::{FutureProducer, FutureRecord, Producer};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{Instrument, Span};
+
+const CORRELATION_ID_HEADER: &str = "correlation-id";
+const SERVICE_HEADERS: [&str; 5] = [
+    CORRELATION_ID_HEADER,
+    "predict-type",
+    "consumed-at",
+    "published-at",
+    "service-version",
+];
 
 pub struct AnyKafkaProducer {
     producer: FutureProducer,
     kafka_config: KafkaConfig,
+    transactional: bool,
 }
 
 impl AnyKafkaProducer {
     pub fn new(kafka_config: KafkaConfig) -> Self {
-        let producer: FutureProducer = ClientConfig::new()
-            .set("bootstrap.servers", kafka_config.bootstrap_servers.join(","))
+        let producer: FutureProducer = kafka_config
+            .build_producer_config()
             .create()
             .expect("Failed to create Kafka producer");
 
-        AnyKafkaProducer { producer, kafka_config }
+        AnyKafkaProducer {
+            producer,
+            kafka_config,
+            transactional: false,
+        }
+    }
+
+    /// Creates a producer, that publishes messages only within transactions (see `send_transaction`).
+    pub fn new_transactional(kafka_config: KafkaConfig, transactional_id: &str) -> Result<Self, Error> {
+        let producer: FutureProducer = kafka_config
+            .build_producer_config()
+            .set("transactional.id", transactional_id)
+            .create()?;
+
+        producer.init_transactions(Duration::from_millis(kafka_config.producer.transaction_timeout_ms))?;
+        tracing::info!("Kafka transactional producer initialized. TransactionalId: {transactional_id}");
+
+        Ok(AnyKafkaProducer {
+            producer,
+            kafka_config,
+            transactional: true,
+        })
+    }
+
+    fn record_key<'a>(&self, message: &'a OutputMessage) -> Option<&'a str> {
+        match self.kafka_config.producer.key_field {
+            MessageKeyField::Id => Some(&message.id),
+            MessageKeyField::ResultPath => message.result_path.as_deref(),
+            MessageKeyField::PredictType => Some(message.predict_type.as_str()),
+            MessageKeyField::None => None,
+        }
+    }
+
+    fn send_timeout(&self) -> Duration {
+        Duration::from_millis(self.kafka_config.producer.send_timeout_ms)
+    }
+}
+
+#[async_trait]
+impl MessageProducer for AnyKafkaProducer {
+    fn is_transactional(&self) -> bool {
+        self.transactional
     }
 
-    pub async fn send(&self, message: InputMessage) -> Result<(), Error> {
+    /// Sends the message of the envelope, keyed by the configured field,
+    /// with the propagated headers of the source record and the service headers.
+    async fn send(&self, envelope: &QueueEnvelope<OutputMessage>) -> Result<(), Error> {
         let topic = self.kafka_config.topics.output.as_str();
+        let message = &envelope.message;
 
-        let serialized_message: String = serde_json::to_string(&message)?;
+        let serialized_message: String = serde_json::to_string(message)?;
+        let key = self.record_key(message);
+        let headers = build_headers(envelope)?;
 
-        let record = FutureRecord::to(topic).payload(&serialized_message).key("");
+        let mut record = FutureRecord::to(topic).payload(&serialized_message).headers(headers);
+
+        if let Some(key) = key {
+            record = record.key(key);
+        }
 
-        match self.producer.send(record, Duration::from_secs(5)).await {
+        let started_at = Instant::now();
+        let result = self.producer.send(record, self.send_timeout()).await;
+        observe_publish(topic, started_at, result.is_ok());
+
+        match result {
             Ok(_) => {
-                log::info!("Sending message to topic: [{topic}]. MessageId: {}", message.id);
+                tracing::info!("Sending message to topic: [{topic}]");
                 Ok(())
             }
             Err((err, _msg)) => Err(Error::from(err)),
         }
     }
+
+    async fn send_raw(
+        &self,
+        topic: &str,
+        key: Option<&[u8]>,
+        payload: Option<&[u8]>,
+        headers: OwnedHeaders,
+    ) -> Result<(), Error> {
+        let mut record: FutureRecord<[u8], [u8]> = FutureRecord::to(topic).headers(headers);
+
+        if let Some(key) = key {
+            record = record.key(key);
+        }
+
+        if let Some(payload) = payload {
+            record = record.payload(payload);
+        }
+
+        let started_at = Instant::now();
+        let result = self.producer.send(record, self.send_timeout()).await;
+        observe_publish(topic, started_at, result.is_ok());
+
+        match result {
+            Ok(_) => Ok(()),
+            Err((err, _msg)) => Err(Error::from(err)),
+        }
+    }
+
+    async fn send_transaction(&self, envelopes: &[QueueEnvelope<OutputMessage>]) -> Result<(), Error> {
+        let transaction_timeout = Duration::from_millis(self.kafka_config.producer.transaction_timeout_ms);
+
+        self.producer.begin_transaction()?;
+
+        let mut result = Ok(());
+        for envelope in envelopes {
+            result = self.send(envelope).instrument(envelope.span()).await;
+            if result.is_err() {
+                break;
+            }
+        }
+
+        if result.is_ok() {
+            result = self
+                .producer
+                .commit_transaction(transaction_timeout)
+                .map_err(Error::from);
+        }
+
+        if result.is_err()
+            && let Err(err) = self.producer.abort_transaction(transaction_timeout)
+        {
+            tracing::error!("Failed to abort Kafka transaction: {err}");
+        }
+
+        result
+    }
+
+    fn flush(&self, timeout: Duration) -> Result<(), Error> {
+        self.producer.flush(timeout)?;
+
+        Ok(())
+    }
+
+    /// Fetches metadata of the topic from the brokers, and fails if the topic is unknown.
+    async fn check_topic(&self, topic: &str, timeout: Duration) -> Result<(), Error> {
+        let producer = self.producer.clone();
+        let topic = topic.to_string();
+
+        // Metadata is fetched with a blocking call, so it is moved off the async runtime.
+        tokio::task::spawn_blocking(move || {
+            let metadata = producer.client().fetch_metadata(Some(&topic), timeout)?;
+
+            match metadata
+                .topics()
+                .iter()
+                .find(|metadata_topic| metadata_topic.name() == topic)
+            {
+                Some(metadata_topic) => match metadata_topic.error() {
+                    Some(err) => bail!("Topic [{topic}] metadata error: {err:?}"),
+                    None => Ok(()),
+                },
+                None => bail!("Topic [{topic}] not found in metadata"),
+            }
+        })
+        .await?
+    }
+}
+
+fn observe_publish(topic: &str, started_at: Instant, succeeded: bool) {
+    METRICS
+        .publish_latency
+        .with_label_values(&[topic])
+        .observe(started_at.elapsed().as_secs_f64());
+
+    if succeeded {
+        METRICS.publish_success.with_label_values(&[topic]).inc();
+    } else {
+        METRICS.publish_failure.with_label_values(&[topic]).inc();
+    }
+}
+
+/// Propagated headers of the source record, followed by the service headers and the trace context of the current span.
+/// The correlation id of the source record is kept, otherwise the message id is used.
+fn build_headers(envelope: &QueueEnvelope<OutputMessage>) -> Result<OwnedHeaders, Error> {
+    let message = &envelope.message;
+
+    let correlation_id = envelope
+        .headers
+        .iter()
+        .find(|header| header.key == CORRELATION_ID_HEADER)
+        .and_then(|header| header.value.clone())
+        .unwrap_or_else(|| message.id.clone());
+    let consumed_at = envelope.consumed_at.map(|millis| millis.to_string());
+    let published_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis().to_string();
+
+    let mut headers = OwnedHeaders::new_with_capacity(envelope.headers.len() + SERVICE_HEADERS.len());
+
+    for header in &envelope.headers {
+        if !SERVICE_HEADERS.contains(&header.key.as_str())
+            && !TRACE_CONTEXT_HEADERS.contains(&header.key.to_ascii_lowercase().as_str())
+        {
+            headers = headers.insert(Header {
+                key: &header.key,
+                value: header.value.as_deref(),
+            });
+        }
+    }
+
+    headers = headers
+        .insert(Header {
+            key: CORRELATION_ID_HEADER,
+            value: Some(&correlation_id),
+        })
+        .insert(Header {
+            key: "predict-type",
+            value: Some(message.predict_type.as_str()),
+        })
+        .insert(Header {
+            key: "consumed-at",
+            value: consumed_at.as_deref(),
+        })
+        .insert(Header {
+            key: "published-at",
+            value: Some(&published_at),
+        })
+        .insert(Header {
+            key: "service-version",
+            value: Some(env!("CARGO_PKG_VERSION")),
+        });
+
+    for (key, value) in context_to_map(&Span::current()) {
+        headers = headers.insert(Header {
+            key: &key,
+            value: Some(&value),
+        });
+    }
+
+    Ok(headers)
 }
@@ -0,0 +1,133 @@
+use crate::kafka::producer::MessageProducer;
+use crate::models::output_message::OutputMessage;
+use crate::models::queue_envelope::QueueEnvelope;
+use anyhow::{Error, bail};
+use async_trait::async_trait;
+use rdkafka::message::OwnedHeaders;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Record published by `MemoryProducer`.
+#[derive(Debug, Clone)]
+pub struct PublishedRecord {
+    pub topic: String,
+    pub key: Option<Vec<u8>>,
+    pub payload: Option<Vec<u8>>,
+}
+
+/// Producer keeping published records in process memory. Intended for tests without Kafka.
+pub struct MemoryProducer {
+    output_topic: String,
+    transactional: bool,
+    failing: AtomicBool,
+    records: Mutex<Vec<PublishedRecord>>,
+    committed_transactions: AtomicUsize,
+}
+
+impl MemoryProducer {
+    pub fn new(output_topic: &str, transactional: bool) -> Self {
+        MemoryProducer {
+            output_topic: output_topic.to_string(),
+            transactional,
+            failing: AtomicBool::new(false),
+            records: Mutex::new(Vec::new()),
+            committed_transactions: AtomicUsize::new(0),
+        }
+    }
+
+    /// Makes every next publish fail, as if the brokers were unavailable.
+    pub fn set_failing(&self, failing: bool) {
+        self.failing.store(failing, Ordering::Relaxed);
+    }
+
+    pub fn records(&self) -> Vec<PublishedRecord> {
+        self.records.lock().expect("poisoned lock").clone()
+    }
+
+    /// Messages published to the output topic.
+    pub fn published_messages(&self) -> Vec<OutputMessage> {
+        self.records()
+            .into_iter()
+            .filter(|record| record.topic == self.output_topic)
+            .filter_map(|record| record.payload)
+            .map(|payload| serde_json::from_slice(&payload).expect("invalid published message"))
+            .collect()
+    }
+
+    pub fn committed_transactions(&self) -> usize {
+        self.committed_transactions.load(Ordering::Relaxed)
+    }
+
+    fn check_available(&self) -> Result<(), Error> {
+        if self.failing.load(Ordering::Relaxed) {
+            bail!("Kafka is unavailable");
+        }
+
+        Ok(())
+    }
+
+    fn output_record(&self, envelope: &QueueEnvelope<OutputMessage>) -> Result<PublishedRecord, Error> {
+        Ok(PublishedRecord {
+            topic: self.output_topic.clone(),
+            key: Some(envelope.message.id.as_bytes().to_vec()),
+            payload: Some(serde_json::to_vec(&envelope.message)?),
+        })
+    }
+}
+
+#[async_trait]
+impl MessageProducer for MemoryProducer {
+    fn is_transactional(&self) -> bool {
+        self.transactional
+    }
+
+    async fn send(&self, envelope: &QueueEnvelope<OutputMessage>) -> Result<(), Error> {
+        self.check_available()?;
+
+        let record = self.output_record(envelope)?;
+        self.records.lock().expect("poisoned lock").push(record);
+
+        Ok(())
+    }
+
+    async fn send_raw(
+        &self,
+        topic: &str,
+        key: Option<&[u8]>,
+        payload: Option<&[u8]>,
+        _headers: OwnedHeaders,
+    ) -> Result<(), Error> {
+        self.check_available()?;
+
+        self.records.lock().expect("poisoned lock").push(PublishedRecord {
+            topic: topic.to_string(),
+            key: key.map(<[u8]>::to_vec),
+            payload: payload.map(<[u8]>::to_vec),
+        });
+
+        Ok(())
+    }
+
+    /// Records all messages and counts the commit, or records nothing if publishing fails.
+    async fn send_transaction(&self, envelopes: &[QueueEnvelope<OutputMessage>]) -> Result<(), Error> {
+        self.check_available()?;
+
+        let records = envelopes
+            .iter()
+            .map(|envelope| self.output_record(envelope))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.records.lock().expect("poisoned lock").extend(records);
+        self.committed_transactions.fetch_add(1, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    fn flush(&self, _timeout: Duration) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn check_topic(&self, _topic: &str, _timeout: Duration) -> Result<(), Error> {
+        self.check_available()
+    }
+}
@@ -0,0 +1,420 @@
+use crate::kafka::producer::MessageProducer;
+use crate::metrics::registry::METRICS;
+use crate::models::input_message::InputMessage;
+use crate::models::message_header::MessageHeader;
+use crate::models::message_source::MessageSource;
+use crate::models::output_message::{OutputMessage, TaskStatus};
+use crate::models::queue_envelope::QueueEnvelope;
+use crate::setting::settings::{KafkaConfig, RedisConfig, ShutdownConfig};
+use crate::storage::object_store::{ObjectStore, build_task_key, transfer};
+use crate::storage::queue::Queue;
+use crate::storage::task_tracker::TaskTracker;
+use crate::telemetry::propagation::context_to_map;
+use anyhow::Error;
+use rdkafka::Message;
+use rdkafka::message::{Header, Headers, OwnedHeaders};
+use serde_json::error::Category;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::Span;
+
+/// Turns consumed records into tasks of the inbox queue, and awaits their completion.
+/// Kept apart from the Kafka consumer, so records can be processed without a broker.
+pub struct MessageProcessor {
+    kafka_config: KafkaConfig,
+    redis_config: RedisConfig,
+    shutdown_config: ShutdownConfig,
+    inbox_queue: Arc<dyn Queue<InputMessage>>,
+    outbox_queue: Arc<dyn Queue<OutputMessage>>,
+    task_tracker: Arc<dyn TaskTracker>,
+    outer_storage: Arc<dyn ObjectStore>,
+    inner_storage: Arc<dyn ObjectStore>,
+    producer: Arc<dyn MessageProducer>,
+}
+
+impl MessageProcessor {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        inbox_queue: Arc<dyn Queue<InputMessage>>,
+        outbox_queue: Arc<dyn Queue<OutputMessage>>,
+        task_tracker: Arc<dyn TaskTracker>,
+        outer_storage: Arc<dyn ObjectStore>,
+        inner_storage: Arc<dyn ObjectStore>,
+        producer: Arc<dyn MessageProducer>,
+        redis_config: RedisConfig,
+        kafka_config: KafkaConfig,
+        shutdown_config: ShutdownConfig,
+    ) -> Self {
+        MessageProcessor {
+            kafka_config,
+            redis_config,
+            shutdown_config,
+            inbox_queue,
+            outbox_queue,
+            task_tracker,
+            outer_storage,
+            inner_storage,
+            producer,
+        }
+    }
+
+    /// Pushes the message to the inbox queue, and returns its task id.
+    /// Returns `None` if the message is rejected, or sent straight to the outbox queue.
+    pub async fn process_message(
+        &self,
+        message: &impl Message,
+        headers: Vec<MessageHeader>,
+    ) -> Result<Option<String>, Error> {
+        let topic = self.kafka_config.topics.input.as_str();
+        let queue_key = self.redis_config.queues.inbox.as_str();
+        let outbox_key = self.redis_config.queues.outbox.as_str();
+
+        METRICS.messages_consumed.with_label_values(&[message.topic()]).inc();
+
+        let Some(payload) = message.payload() else {
+            self.reject_message(message, "Empty payload", "empty").await?;
+            return Ok(None);
+        };
+
+        let source = MessageSource {
+            topic: message.topic().to_string(),
+            partition: message.partition(),
+            offset: message.offset(),
+        };
+
+        match serde_json::from_slice::<InputMessage>(payload) {
+            Ok(mut message) => {
+                METRICS.messages_parsed.with_label_values(&[topic]).inc();
+
+                let span = Span::current();
+                span.record("id", message.id.as_str());
+                span.record("predict_type", message.predict_type.as_str());
+
+                let inner_image_path = build_task_key(&message.id, &message.image_path);
+
+                let transferred = transfer(
+                    self.outer_storage.as_ref(),
+                    &message.image_path,
+                    self.inner_storage.as_ref(),
+                    &inner_image_path,
+                )
+                .await?;
+
+                if !transferred {
+                    tracing::warn!(
+                        "Image [{}] not found in outer storage. Message will be sent to queue: [{outbox_key}]",
+                        message.image_path
+                    );
+
+                    let output_message = OutputMessage::with_error(
+                        message.id,
+                        message.predict_type,
+                        TaskStatus::Rejected,
+                        "IMAGE_NOT_FOUND",
+                        format!("Image not found: {}", message.image_path),
+                    );
+                    let envelope = QueueEnvelope::new(output_message)
+                        .with_headers(headers)
+                        .with_source(source)
+                        .with_trace_context(context_to_map(&Span::current()));
+                    self.outbox_queue.push(outbox_key, envelope).await?;
+                    return Ok(None);
+                }
+
+                message.image_path = inner_image_path;
+                let message_id = message.id.clone();
+
+                // Tracked before the push, so a fast worker cannot complete the task before it is tracked.
+                self.task_tracker.track(std::slice::from_ref(&message_id)).await?;
+                let envelope = QueueEnvelope::new(message)
+                    .with_headers(headers)
+                    .with_source(source)
+                    .with_trace_context(context_to_map(&Span::current()));
+                self.inbox_queue.push(queue_key, envelope).await?;
+                tracing::info!("Message consumed from topic: [{topic}] and pushed to queue: [{queue_key}]");
+
+                Ok(Some(message_id))
+            }
+            Err(err) => {
+                let category = match err.classify() {
+                    Category::Io => "io",
+                    Category::Syntax => "syntax",
+                    Category::Data => "schema",
+                    Category::Eof => "eof",
+                };
+
+                self.reject_message(message, &err.to_string(), category).await?;
+
+                Ok(None)
+            }
+        }
+    }
+
+    /// Forwards a record that cannot be processed to the DLQ topic verbatim, with headers describing the reason.
+    async fn reject_message(&self, message: &impl Message, reason: &str, category: &str) -> Result<(), Error> {
+        let topic = message.topic();
+        let partition = message.partition();
+        let offset = message.offset();
+
+        tracing::warn!(
+            "Rejected message at topic [{topic}], partition {partition}, offset {offset} ({category}): {reason}"
+        );
+        METRICS.messages_rejected.with_label_values(&[topic]).inc();
+
+        let Some(dlq_topic) = self.kafka_config.topics.dlq.as_deref() else {
+            tracing::warn!("DLQ topic is not configured. Rejected message will be skipped");
+            return Ok(());
+        };
+
+        let rejected_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis().to_string();
+        let source_timestamp = message.timestamp().to_millis().map(|millis| millis.to_string());
+
+        let mut headers = OwnedHeaders::new();
+        if let Some(source_headers) = message.headers() {
+            for header in source_headers.iter() {
+                headers = headers.insert(header);
+            }
+        }
+
+        let headers = headers
+            .insert(Header {
+                key: "dlq.error",
+                value: Some(reason),
+            })
+            .insert(Header {
+                key: "dlq.error.category",
+                value: Some(category),
+            })
+            .insert(Header {
+                key: "dlq.source.topic",
+                value: Some(topic),
+            })
+            .insert(Header {
+                key: "dlq.source.partition",
+                value: Some(&partition.to_string()),
+            })
+            .insert(Header {
+                key: "dlq.source.offset",
+                value: Some(&offset.to_string()),
+            })
+            .insert(Header {
+                key: "dlq.source.timestamp",
+                value: source_timestamp.as_deref(),
+            })
+            .insert(Header {
+                key: "dlq.rejected_at",
+                value: Some(&rejected_at),
+            });
+
+        self.producer
+            .send_raw(dlq_topic, message.key(), message.payload(), headers)
+            .await?;
+        tracing::info!("Rejected message sent to DLQ topic: [{dlq_topic}]");
+
+        Ok(())
+    }
+
+    /// Waits until all tasks of the batch are completed, or the batch timeout expires.
+    /// Once the token is cancelled, the tasks are awaited at most for the drain share of the shutdown timeout.
+    /// Tasks left after the timeout are no longer tracked.
+    pub async fn wait_for_tasks(&self, task_ids: &[String], token: &CancellationToken) -> Result<(), Error> {
+        let batch_timeout = Duration::from_millis(self.kafka_config.batch_timeout_ms);
+        let drain_timeout = self.shutdown_config.drain_timeout();
+        let poll_delay = Duration::from_millis(self.redis_config.poll_delay_ms);
+        let started_at = Instant::now();
+        let mut deadline = started_at + batch_timeout;
+        let mut draining = false;
+
+        loop {
+            let pending = self.task_tracker.pending(task_ids).await?;
+            tracing::debug!("Batch tasks check: pending={pending}, total={}", task_ids.len());
+
+            if pending == 0 {
+                METRICS.batch_wait.observe(started_at.elapsed().as_secs_f64());
+                return Ok(());
+            }
+
+            if Instant::now() >= deadline {
+                let timeout = if draining { drain_timeout } else { batch_timeout };
+                tracing::warn!(
+                    "Batch timeout of {timeout:?} expired with {pending} of {} tasks pending. Batch will be committed",
+                    task_ids.len()
+                );
+
+                METRICS.batch_wait.observe(started_at.elapsed().as_secs_f64());
+                return self.task_tracker.complete(task_ids).await;
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(poll_delay) => {}
+                _ = token.cancelled(), if !draining => {
+                    draining = true;
+                    deadline = deadline.min(Instant::now() + drain_timeout);
+                    tracing::info!("Shutdown requested, batch tasks will be awaited at most {drain_timeout:?}");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{INBOX, INPUT_TOPIC, OUTBOX, TestService, record, settings, task_payload};
+
+    #[tokio::test]
+    async fn pushes_task_with_transferred_image_to_inbox() {
+        let service = TestService::new(settings());
+        service
+            .outer_storage
+            .put_object("images/1.png", b"image".to_vec())
+            .await
+            .unwrap();
+
+        let headers = vec![MessageHeader {
+            key: "correlation-id".to_string(),
+            value: Some("abc".to_string()),
+        }];
+        let payload = task_payload("1", "images/1.png");
+
+        let task_id = service
+            .processor()
+            .process_message(&record(Some(&payload)), headers)
+            .await
+            .unwrap();
+
+        assert_eq!(task_id.as_deref(), Some("1"));
+        assert_eq!(service.task_tracker.pending(&["1".to_string()]).await.unwrap(), 1);
+        assert_eq!(service.inner_storage.get_object("1/1.png").await.unwrap(), Some(b"image".to_vec()));
+
+        let delivery = service.inbox_queue.pop(INBOX).await.unwrap().unwrap();
+        let envelope = delivery.envelope;
+        assert_eq!(envelope.message.image_path, "1/1.png");
+        assert_eq!(envelope.headers.len(), 1);
+        assert_eq!(envelope.headers[0].value.as_deref(), Some("abc"));
+
+        let source = envelope.source.unwrap();
+        assert_eq!((source.topic.as_str(), source.partition, source.offset), (INPUT_TOPIC, 0, 7));
+
+        assert_eq!(service.outbox_queue.len(OUTBOX).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn sends_task_with_missing_image_to_outbox_as_rejected() {
+        let service = TestService::new(settings());
+        let payload = task_payload("2", "images/2.png");
+
+        let task_id = service
+            .processor()
+            .process_message(&record(Some(&payload)), Vec::new())
+            .await
+            .unwrap();
+
+        assert_eq!(task_id, None);
+        assert_eq!(service.task_tracker.pending(&["2".to_string()]).await.unwrap(), 0);
+        assert_eq!(service.inbox_queue.len(INBOX).await.unwrap(), 0);
+
+        let delivery = service.outbox_queue.pop(OUTBOX).await.unwrap().unwrap();
+        let message = delivery.envelope.message;
+        assert_eq!(message.id, "2");
+        assert_eq!(message.status, TaskStatus::Rejected);
+        assert_eq!(message.error_code.as_deref(), Some("IMAGE_NOT_FOUND"));
+    }
+
+    #[tokio::test]
+    async fn skips_invalid_records_without_dlq_topic() {
+        let service = TestService::new(settings());
+        let processor = service.processor();
+
+        for payload in [None, Some("not a task"), Some(r#"{"id": "3"}"#)] {
+            let task_id = processor.process_message(&record(payload), Vec::new()).await.unwrap();
+
+            assert_eq!(task_id, None);
+        }
+
+        assert_eq!(service.inbox_queue.len(INBOX).await.unwrap(), 0);
+        assert_eq!(service.outbox_queue.len(OUTBOX).await.unwrap(), 0);
+        assert!(service.producer.records().is_empty());
+    }
+
+    #[tokio::test]
+    async fn forwards_invalid_record_to_dlq_topic_verbatim() {
+        let mut settings = settings();
+        settings.kafka.topics.dlq = Some("tasks_dlq".to_string());
+        let service = TestService::new(settings);
+
+        let task_id = service
+            .processor()
+            .process_message(&record(Some("not a task")), Vec::new())
+            .await
+            .unwrap();
+
+        assert_eq!(task_id, None);
+        assert_eq!(service.inbox_queue.len(INBOX).await.unwrap(), 0);
+
+        let records = service.producer.records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].topic, "tasks_dlq");
+        assert_eq!(records[0].payload.as_deref(), Some(b"not a task".as_slice()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_for_tasks_until_completed() {
+        let service = TestService::new(settings());
+        let task_ids = vec!["4".to_string()];
+        service.task_tracker.track(&task_ids).await.unwrap();
+
+        let task_tracker = service.task_tracker.clone();
+        let completed_ids = task_ids.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            task_tracker.complete(&completed_ids).await.unwrap();
+        });
+
+        let started_at = Instant::now();
+        service
+            .processor()
+            .wait_for_tasks(&task_ids, &CancellationToken::new())
+            .await
+            .unwrap();
+
+        assert!(started_at.elapsed() < Duration::from_millis(100));
+        assert_eq!(service.task_tracker.pending(&task_ids).await.unwrap(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stops_tracking_tasks_after_batch_timeout() {
+        let service = TestService::new(settings());
+        let task_ids = vec!["5".to_string()];
+        service.task_tracker.track(&task_ids).await.unwrap();
+
+        let started_at = Instant::now();
+        service
+            .processor()
+            .wait_for_tasks(&task_ids, &CancellationToken::new())
+            .await
+            .unwrap();
+
+        assert!(started_at.elapsed() >= Duration::from_millis(100));
+        assert_eq!(service.task_tracker.pending(&task_ids).await.unwrap(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stops_waiting_for_tasks_after_drain_timeout_on_shutdown() {
+        let service = TestService::new(settings());
+        let task_ids = vec!["6".to_string()];
+        service.task_tracker.track(&task_ids).await.unwrap();
+
+        let token = CancellationToken::new();
+        token.cancel();
+
+        let started_at = Instant::now();
+        service.processor().wait_for_tasks(&task_ids, &token).await.unwrap();
+
+        let elapsed = started_at.elapsed();
+        assert!(elapsed >= Duration::from_millis(50) && elapsed < Duration::from_millis(100));
+        assert_eq!(service.task_tracker.pending(&task_ids).await.unwrap(), 0);
+    }
+}
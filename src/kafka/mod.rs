@@ -1,2 +1,8 @@
+pub mod consumer_context;
 pub mod kafka_consumer;
 pub mod kafka_producer;
+#[cfg(test)]
+pub mod memory_producer;
+pub mod message_processor;
+pub mod producer;
+pub mod start_position;
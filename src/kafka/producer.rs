@@ -0,0 +1,33 @@
+use crate::models::output_message::OutputMessage;
+use crate::models::queue_envelope::QueueEnvelope;
+use anyhow::Error;
+use async_trait::async_trait;
+use rdkafka::message::OwnedHeaders;
+use std::time::Duration;
+
+#[async_trait]
+pub trait MessageProducer: Send + Sync {
+    /// Whether messages are published only within transactions (see `send_transaction`).
+    fn is_transactional(&self) -> bool;
+
+    /// Sends the message of the envelope to the output topic.
+    async fn send(&self, envelope: &QueueEnvelope<OutputMessage>) -> Result<(), Error>;
+
+    /// Sends a record as is, without serialization.
+    async fn send_raw(
+        &self,
+        topic: &str,
+        key: Option<&[u8]>,
+        payload: Option<&[u8]>,
+        headers: OwnedHeaders,
+    ) -> Result<(), Error>;
+
+    /// Publishes the messages in one transaction: either all of them become visible to consumers, or none.
+    async fn send_transaction(&self, envelopes: &[QueueEnvelope<OutputMessage>]) -> Result<(), Error>;
+
+    /// Waits until all queued records are delivered.
+    fn flush(&self, timeout: Duration) -> Result<(), Error>;
+
+    /// Fails if the topic is unknown to the brokers.
+    async fn check_topic(&self, topic: &str, timeout: Duration) -> Result<(), Error>;
+}
@@ -0,0 +1,67 @@
+use crate::setting::settings::KafkaConfig;
+use anyhow::Error;
+use rdkafka::consumer::{BaseConsumer, CommitMode, Consumer};
+use rdkafka::{Offset, TopicPartitionList};
+use std::time::Duration;
+
+const METADATA_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Position the consumer group is moved to, to replay historical tasks.
+#[derive(Debug, Clone)]
+pub enum StartPosition {
+    /// Every partition is moved to the first message at or after the time (in milliseconds since the Unix epoch).
+    Timestamp(i64),
+    /// Partitions are moved to the given offsets.
+    Offsets(Vec<(i32, i64)>),
+}
+
+/// Commits the start position for the consumer group of the input topic, and returns the committed offsets.
+/// The group must have no active members, otherwise the commit is rejected by the broker.
+pub async fn apply(kafka_config: &KafkaConfig, start_position: StartPosition) -> Result<TopicPartitionList, Error> {
+    let kafka_config = kafka_config.clone();
+
+    // Metadata, offsets and the commit are fetched with blocking calls, so they are moved off the async runtime.
+    tokio::task::spawn_blocking(move || apply_blocking(&kafka_config, &start_position)).await?
+}
+
+fn apply_blocking(kafka_config: &KafkaConfig, start_position: &StartPosition) -> Result<TopicPartitionList, Error> {
+    let topic = kafka_config.topics.input.as_str();
+    let consumer: BaseConsumer = kafka_config.build_kafka_config().create()?;
+
+    let mut offsets = TopicPartitionList::new();
+
+    match start_position {
+        StartPosition::Timestamp(timestamp_ms) => {
+            let metadata = consumer.fetch_metadata(Some(topic), METADATA_TIMEOUT)?;
+            let partitions = metadata
+                .topics()
+                .iter()
+                .filter(|metadata_topic| metadata_topic.name() == topic)
+                .flat_map(|metadata_topic| metadata_topic.partitions())
+                .map(|partition| partition.id());
+
+            for partition in partitions {
+                offsets.add_partition_offset(topic, partition, Offset::Offset(*timestamp_ms))?;
+            }
+
+            offsets = consumer.offsets_for_times(offsets, METADATA_TIMEOUT)?;
+
+            // Partitions without messages after the timestamp are moved to their end.
+            for element in offsets.clone().elements() {
+                if element.offset() == Offset::End {
+                    let (_, high) = consumer.fetch_watermarks(topic, element.partition(), METADATA_TIMEOUT)?;
+                    offsets.set_partition_offset(topic, element.partition(), Offset::Offset(high))?;
+                }
+            }
+        }
+        StartPosition::Offsets(partition_offsets) => {
+            for (partition, offset) in partition_offsets {
+                offsets.add_partition_offset(topic, *partition, Offset::Offset(*offset))?;
+            }
+        }
+    }
+
+    consumer.commit(&offsets, CommitMode::Sync)?;
+
+    Ok(offsets)
+}
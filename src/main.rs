@@ -1,22 +1,47 @@
+use crate::admin::{offsets_command, poison_command};
 use crate::daemon::outbox_daemon::OutboxDaemon;
+use crate::health::health_check::{HealthCheck, KafkaCheck, RedisCheck, StorageCheck};
+use crate::health::readiness::Readiness;
+use crate::http::server;
 use crate::kafka::kafka_consumer::AnyKafkaConsumer;
 use crate::kafka::kafka_producer::AnyKafkaProducer;
-use crate::setting::settings::Settings;
+use crate::kafka::producer::MessageProducer;
+use crate::metrics::queue_sampler::QueueSampler;
+use crate::models::input_message::InputMessage;
+use crate::models::output_message::OutputMessage;
+use crate::setting::settings::{QueueBackend, RedisQueueType, Settings, StorageBackend};
+use crate::storage::memory_queue::MemoryQueue;
+use crate::storage::memory_storage::MemoryStorage;
+use crate::storage::memory_task_tracker::MemoryTaskTracker;
+use crate::storage::object_store::ObjectStore;
+use crate::storage::poison_queue::PoisonQueue;
+use crate::storage::queue::Queue;
 use crate::storage::redis_queue::RedisQueue;
+use crate::storage::redis_stream_queue::RedisStreamQueue;
+use crate::storage::redis_task_tracker::RedisTaskTracker;
 use crate::storage::s3_storage::S3Storage;
-use env_logger::Builder;
-use log::LevelFilter;
+use crate::storage::task_tracker::TaskTracker;
+use crate::telemetry::tracer;
+use futures::future::join_all;
 use redis::Client as RedisClient;
 use std::error::Error;
-use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::signal;
+use tokio_util::sync::CancellationToken;
 
+mod admin;
 mod daemon;
+mod health;
+mod http;
 mod kafka;
+mod metrics;
 mod models;
 mod setting;
 mod storage;
+mod telemetry;
+#[cfg(test)]
+mod test_support;
 mod utils;
 
 #[tokio::main]
@@ -25,53 +50,273 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     let shared_setting = Arc::new(settings);
 
-    Builder::new()
-        .filter_level(LevelFilter::from_str(shared_setting.logging.log_level.as_str()).unwrap_or(LevelFilter::Info))
-        .init();
+    let tracer_provider = tracer::init(&shared_setting.logging, &shared_setting.telemetry)
+        .map_err(|err| format!("Failed to initialize tracing: {err}"))?;
 
-    log::info!("Settings:\n{}", shared_setting.json_pretty());
+    tracing::info!("Settings:\n{}", shared_setting.json_pretty());
 
-    let _ = S3Storage::new(shared_setting.s3.clone()).await;
-    log::info!("Successfully creates a new client from S3");
+    shared_setting
+        .kafka
+        .validate()
+        .map_err(|err| format!("Failed to validate Kafka settings: {err}"))?;
+    shared_setting
+        .shutdown
+        .validate(&shared_setting.redis)
+        .map_err(|err| format!("Failed to validate shutdown settings: {err}"))?;
 
-    let connection_url = shared_setting.redis.build_redis_connect_url();
-    let client = RedisClient::open(connection_url)?;
-    let multiplexed_connection = client
-        .get_multiplexed_async_connection()
-        .await
-        .map_err(|err| format!("Cannot connect to Redis. Error: {err}"))?;
-    log::info!("Successfully connect with Redis");
+    // Entries of a Redis list are removed when read, so they would be lost if the transaction fails.
+    if shared_setting.kafka.producer.transactional_id.is_some()
+        && shared_setting.backend.queue == QueueBackend::Redis
+        && shared_setting.redis.queue_type == RedisQueueType::List
+    {
+        return Err("Transactional producer requires `redis.queue_type: stream`".into());
+    }
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    if args.first().is_some_and(|command| command == "offsets") {
+        return Ok(offsets_command::run(&args[1..], &shared_setting.kafka).await?);
+    }
+
+    let redis_connection = match shared_setting.backend.queue {
+        QueueBackend::Redis => {
+            let connection_url = shared_setting.redis.build_redis_connect_url();
+            let client = RedisClient::open(connection_url)?;
+            let multiplexed_connection = client
+                .get_multiplexed_async_connection()
+                .await
+                .map_err(|err| format!("Cannot connect to Redis. Error: {err}"))?;
+            tracing::info!("Successfully connect with Redis");
+
+            Some(multiplexed_connection)
+        }
+        QueueBackend::Memory => None,
+    };
+
+    let poison_queue = redis_connection
+        .as_ref()
+        .map(|connection| Arc::new(PoisonQueue::new(connection.clone(), shared_setting.redis.queues.poison.clone())));
+
+    // One queue instance serves both the inbox (tasks) and the outbox (results).
+    let (inbox_queue, outbox_queue): (Arc<dyn Queue<InputMessage>>, Arc<dyn Queue<OutputMessage>>) =
+        match (redis_connection.clone(), poison_queue.clone()) {
+            (Some(multiplexed_connection), Some(poison_queue)) => match shared_setting.redis.queue_type {
+                RedisQueueType::List => {
+                    let queue = Arc::new(RedisQueue::new(multiplexed_connection, poison_queue));
+                    (queue.clone(), queue)
+                }
+                RedisQueueType::Stream => {
+                    let queue = Arc::new(RedisStreamQueue::new(
+                        multiplexed_connection,
+                        shared_setting.redis.stream.clone(),
+                        poison_queue,
+                    ));
+                    (queue.clone(), queue)
+                }
+            },
+            _ => {
+                tracing::warn!("Using in-memory queue instead of Redis");
+
+                let queue = Arc::new(MemoryQueue::new(&shared_setting.redis.queues.poison));
+                (queue.clone(), queue)
+            }
+        };
+
+    let task_tracker: Arc<dyn TaskTracker> = match &redis_connection {
+        Some(connection) => {
+            Arc::new(RedisTaskTracker::new(connection.clone(), shared_setting.redis.queues.pending.clone()))
+        }
+        None => Arc::new(MemoryTaskTracker::new()),
+    };
+
+    if args.first().is_some_and(|command| command == "poison") {
+        let poison_queue = poison_queue.ok_or("Poison queue requires the Redis backend")?;
+
+        return Ok(poison_command::run(
+            &args[1..],
+            &poison_queue,
+            &shared_setting.redis.queues,
+            inbox_queue.as_ref(),
+            outbox_queue.as_ref(),
+        )
+        .await?);
+    }
+
+    let (outer_storage, inner_storage): (Arc<dyn ObjectStore>, Arc<dyn ObjectStore>) =
+        match shared_setting.backend.storage {
+            StorageBackend::S3 => {
+                let outer_storage = S3Storage::new(shared_setting.s3.outer.clone()).await;
+                tracing::info!("Successfully creates a new client for outer S3 storage");
+
+                let inner_storage = S3Storage::new(shared_setting.s3.inner.clone()).await;
+                tracing::info!("Successfully creates a new client for inner S3 storage");
+
+                (Arc::new(outer_storage), Arc::new(inner_storage))
+            }
+            StorageBackend::Memory => {
+                tracing::warn!("Using in-memory storages instead of S3");
+
+                (
+                    Arc::new(MemoryStorage::new(&shared_setting.s3.outer.bucket)),
+                    Arc::new(MemoryStorage::new(&shared_setting.s3.inner.bucket)),
+                )
+            }
+        };
+
+    let kafka_producer: Arc<dyn MessageProducer> = Arc::new(AnyKafkaProducer::new(shared_setting.kafka.clone()));
+
+    // Transactional producer publishes only within transactions, so the consumer keeps a plain one for the DLQ.
+    let outbox_producer: Arc<dyn MessageProducer> = match &shared_setting.kafka.producer.transactional_id {
+        Some(transactional_id) => {
+            Arc::new(AnyKafkaProducer::new_transactional(shared_setting.kafka.clone(), transactional_id)?)
+        }
+        None => kafka_producer.clone(),
+    };
+
+    let readiness_timeout = Duration::from_millis(shared_setting.http.readiness_timeout_ms);
+    let kafka_topics = [
+        Some(&shared_setting.kafka.topics.input),
+        Some(&shared_setting.kafka.topics.output),
+        shared_setting.kafka.topics.dlq.as_ref(),
+    ]
+    .into_iter()
+    .flatten()
+    .cloned()
+    .collect();
+
+    let mut health_checks: Vec<Arc<dyn HealthCheck>> = vec![
+        Arc::new(KafkaCheck::new(kafka_producer.clone(), kafka_topics, readiness_timeout)),
+        Arc::new(StorageCheck::new("s3_outer", outer_storage.clone())),
+        Arc::new(StorageCheck::new("s3_inner", inner_storage.clone())),
+    ];
 
-    let redis_queue = Arc::new(RedisQueue::new(multiplexed_connection, shared_setting.redis.clone()));
+    if let Some(connection) = &redis_connection {
+        health_checks.push(Arc::new(RedisCheck::new(connection.clone())));
+    }
 
-    let kafka_consumer =
-        AnyKafkaConsumer::new(redis_queue.clone(), shared_setting.redis.clone(), shared_setting.kafka.clone());
-    let kafka_producer = AnyKafkaProducer::new(shared_setting.kafka.clone());
+    let readiness = Arc::new(Readiness::new(health_checks, readiness_timeout));
 
-    let outbox_daemon = OutboxDaemon::new(redis_queue.clone(), shared_setting.clone(), kafka_producer);
+    let queue_sampler = QueueSampler::new(
+        inbox_queue.clone(),
+        outbox_queue.clone(),
+        shared_setting.redis.queues.inbox.clone(),
+        shared_setting.redis.queues.outbox.clone(),
+        Duration::from_millis(shared_setting.metrics.queue_sample_interval_ms),
+    );
 
+    let kafka_consumer = AnyKafkaConsumer::new(
+        inbox_queue,
+        outbox_queue.clone(),
+        task_tracker.clone(),
+        outer_storage.clone(),
+        inner_storage.clone(),
+        kafka_producer.clone(),
+        shared_setting.redis.clone(),
+        shared_setting.kafka.clone(),
+        shared_setting.shutdown.clone(),
+    );
+
+    let outbox_daemon = OutboxDaemon::new(
+        outbox_queue,
+        task_tracker.clone(),
+        outer_storage.clone(),
+        inner_storage.clone(),
+        shared_setting.clone(),
+        outbox_producer,
+    );
+
+    let shutdown_token = CancellationToken::new();
+
+    let consumer_token = shutdown_token.clone();
     let consumer_handle = tokio::spawn(async move {
-        log::info!("Kafka consumer task started");
+        tracing::info!("Kafka consumer task started");
 
-        if let Err(err) = kafka_consumer.consume().await {
-            log::error!("Kafka consumer error: {err}");
+        // Without the consumer the service cannot do its work, so the whole service is stopped.
+        if let Err(err) = kafka_consumer.consume(consumer_token.clone()).await {
+            tracing::error!("Kafka consumer error: {err:#}. Service will be stopped");
+            consumer_token.cancel();
         }
     });
 
+    let outbox_token = shutdown_token.clone();
     let outbox_handle = tokio::spawn(async move {
-        log::info!("Outbox daemon task started");
+        tracing::info!("Outbox daemon task started");
 
-        if let Err(err) = outbox_daemon.start().await {
-            log::error!("Outbox daemon error: {err}");
+        // Without the daemon results are not published, so the whole service is stopped.
+        if let Err(err) = outbox_daemon.start(outbox_token.clone()).await {
+            tracing::error!("Outbox daemon error: {err:#}. Service will be stopped");
+            outbox_token.cancel();
         }
     });
 
-    log::info!("Service started successfully. Press Ctrl+C to stop.");
-    signal::ctrl_c().await?;
-    log::info!("Shutdown signal received");
+    let sampler_token = shutdown_token.clone();
+    let sampler_handle = tokio::spawn(queue_sampler.start(sampler_token));
+
+    let mut handles = vec![consumer_handle, outbox_handle, sampler_handle];
+
+    if shared_setting.http.enabled {
+        let http_address = shared_setting.http.address.clone();
+        let http_token = shutdown_token.clone();
+
+        handles.push(tokio::spawn(async move {
+            if let Err(err) = server::serve(&http_address, readiness, http_token).await {
+                tracing::error!("HTTP server error: {err}");
+            }
+        }));
+    }
+
+    tracing::info!("Service started successfully. Press Ctrl+C to stop.");
+
+    // The token is cancelled before the signal only by a task that failed fatally.
+    let failed = tokio::select! {
+        result = wait_for_shutdown_signal() => {
+            result?;
+            tracing::info!("Shutdown signal received");
+            false
+        }
+        _ = shutdown_token.cancelled() => true,
+    };
+
+    shutdown_token.cancel();
+
+    let shutdown_timeout = Duration::from_millis(shared_setting.shutdown.timeout_ms);
 
-    consumer_handle.abort();
-    outbox_handle.abort();
+    if tokio::time::timeout(shutdown_timeout, join_all(handles.iter_mut()))
+        .await
+        .is_err()
+    {
+        tracing::warn!("Graceful shutdown did not complete in {shutdown_timeout:?}. Remaining tasks will be aborted");
+
+        for handle in &handles {
+            handle.abort();
+        }
+    }
+
+    if let Err(err) = tracer_provider.shutdown() {
+        tracing::warn!("Failed to shut down tracer provider: {err}");
+    }
+
+    tracing::info!("Service stopped");
+
+    if failed {
+        return Err("Service stopped after a fatal error".into());
+    }
 
     Ok(())
 }
+
+/// Waits for Ctrl+C or, on Unix, SIGTERM (sent by container runtimes).
+async fn wait_for_shutdown_signal() -> Result<(), std::io::Error> {
+    #[cfg(unix)]
+    {
+        let mut sigterm = signal::unix::signal(signal::unix::SignalKind::terminate())?;
+
+        tokio::select! {
+            result = signal::ctrl_c() => result,
+            _ = sigterm.recv() => Ok(()),
+        }
+    }
+
+    #[cfg(not(unix))]
+    signal::ctrl_c().await
+}
@@ -0,0 +1,2 @@
+pub mod queue_sampler;
+pub mod registry;
@@ -0,0 +1,60 @@
+use crate::metrics::registry::METRICS;
+use crate::models::input_message::InputMessage;
+use crate::models::output_message::OutputMessage;
+use crate::storage::queue::Queue;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// Periodically samples lengths of the inbox and outbox queues into the `queue_length` gauge.
+pub struct QueueSampler {
+    inbox_queue: Arc<dyn Queue<InputMessage>>,
+    outbox_queue: Arc<dyn Queue<OutputMessage>>,
+    inbox_key: String,
+    outbox_key: String,
+    interval: Duration,
+}
+
+impl QueueSampler {
+    pub fn new(
+        inbox_queue: Arc<dyn Queue<InputMessage>>,
+        outbox_queue: Arc<dyn Queue<OutputMessage>>,
+        inbox_key: String,
+        outbox_key: String,
+        interval: Duration,
+    ) -> Self {
+        QueueSampler {
+            inbox_queue,
+            outbox_queue,
+            inbox_key,
+            outbox_key,
+            interval,
+        }
+    }
+
+    /// Samples the queues until the token is cancelled.
+    pub async fn start(self, token: CancellationToken) {
+        while !token.is_cancelled() {
+            match self.inbox_queue.len(&self.inbox_key).await {
+                Ok(len) => METRICS
+                    .queue_length
+                    .with_label_values(&[&self.inbox_key])
+                    .set(len as i64),
+                Err(err) => tracing::warn!("Failed to sample length of queue [{}]: {err}", self.inbox_key),
+            }
+
+            match self.outbox_queue.len(&self.outbox_key).await {
+                Ok(len) => METRICS
+                    .queue_length
+                    .with_label_values(&[&self.outbox_key])
+                    .set(len as i64),
+                Err(err) => tracing::warn!("Failed to sample length of queue [{}]: {err}", self.outbox_key),
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(self.interval) => {}
+                _ = token.cancelled() => {}
+            }
+        }
+    }
+}
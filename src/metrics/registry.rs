@@ -0,0 +1,90 @@
+use anyhow::Error;
+use prometheus::{Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder};
+use std::sync::LazyLock;
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(|| Metrics::new().expect("Failed to register metrics"));
+
+/// Metrics of the connector pipeline, exposed on the `/metrics` endpoint.
+pub struct Metrics {
+    registry: Registry,
+    pub messages_consumed: IntCounterVec,
+    pub messages_parsed: IntCounterVec,
+    pub messages_rejected: IntCounterVec,
+    pub queue_length: IntGaugeVec,
+    pub publish_success: IntCounterVec,
+    pub publish_failure: IntCounterVec,
+    pub publish_latency: HistogramVec,
+    pub batch_wait: Histogram,
+    pub storage_bytes: IntCounterVec,
+    pub storage_duration: HistogramVec,
+}
+
+impl Metrics {
+    fn new() -> Result<Self, Error> {
+        let registry = Registry::new_custom(Some("guelderose".to_string()), None)?;
+
+        let messages_consumed =
+            IntCounterVec::new(Opts::new("messages_consumed_total", "Messages consumed from Kafka"), &["topic"])?;
+        let messages_parsed = IntCounterVec::new(
+            Opts::new("messages_parsed_total", "Consumed messages parsed successfully"),
+            &["topic"],
+        )?;
+        let messages_rejected = IntCounterVec::new(
+            Opts::new("messages_rejected_total", "Consumed messages rejected as unprocessable"),
+            &["topic"],
+        )?;
+        let queue_length = IntGaugeVec::new(Opts::new("queue_length", "Messages waiting in the queue"), &["queue"])?;
+        let publish_success =
+            IntCounterVec::new(Opts::new("publish_success_total", "Records published to Kafka"), &["topic"])?;
+        let publish_failure = IntCounterVec::new(
+            Opts::new("publish_failure_total", "Records failed to be published to Kafka"),
+            &["topic"],
+        )?;
+        let publish_latency = HistogramVec::new(
+            HistogramOpts::new("publish_latency_seconds", "Time to publish a record to Kafka"),
+            &["topic"],
+        )?;
+        let batch_wait = Histogram::with_opts(
+            HistogramOpts::new("batch_wait_seconds", "Time waiting for tasks of a batch to complete")
+                .buckets(prometheus::exponential_buckets(0.1, 2.0, 14)?),
+        )?;
+        let storage_bytes = IntCounterVec::new(
+            Opts::new("storage_bytes_total", "Bytes transferred from and to S3"),
+            &["bucket", "operation"],
+        )?;
+        let storage_duration = HistogramVec::new(
+            HistogramOpts::new("storage_duration_seconds", "Duration of S3 operations"),
+            &["bucket", "operation"],
+        )?;
+
+        registry.register(Box::new(messages_consumed.clone()))?;
+        registry.register(Box::new(messages_parsed.clone()))?;
+        registry.register(Box::new(messages_rejected.clone()))?;
+        registry.register(Box::new(queue_length.clone()))?;
+        registry.register(Box::new(publish_success.clone()))?;
+        registry.register(Box::new(publish_failure.clone()))?;
+        registry.register(Box::new(publish_latency.clone()))?;
+        registry.register(Box::new(batch_wait.clone()))?;
+        registry.register(Box::new(storage_bytes.clone()))?;
+        registry.register(Box::new(storage_duration.clone()))?;
+
+        Ok(Metrics {
+            registry,
+            messages_consumed,
+            messages_parsed,
+            messages_rejected,
+            queue_length,
+            publish_success,
+            publish_failure,
+            publish_latency,
+            batch_wait,
+            storage_bytes,
+            storage_duration,
+        })
+    }
+
+    /// Renders all metrics in the Prometheus text format.
+    pub fn encode(&self) -> Result<String, Error> {
+        Ok(TextEncoder::new().encode_to_string(&self.registry.gather())?)
+    }
+}
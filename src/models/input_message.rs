@@ -8,9 +8,19 @@ pub struct InputMessage {
     pub predict_type: PredictType,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum PredictType {
     TypeOne,
     TypeTwo,
 }
+
+impl PredictType {
+    /// Name of the type, as it is serialized.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PredictType::TypeOne => "TYPE_ONE",
+            PredictType::TypeTwo => "TYPE_TWO",
+        }
+    }
+}
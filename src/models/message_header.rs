@@ -0,0 +1,9 @@
+use serde::{Deserialize, Serialize};
+
+/// Kafka record header, carried through the queue along with the message.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MessageHeader {
+    pub key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+}
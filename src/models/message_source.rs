@@ -0,0 +1,9 @@
+use serde::{Deserialize, Serialize};
+
+/// Position of the Kafka record the message was consumed from.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MessageSource {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+}
@@ -1 +1,6 @@
-pub mod input_message;
\ No newline at end of file
+pub mod input_message;
+pub mod message_header;
+pub mod message_source;
+pub mod output_message;
+pub mod poison_entry;
+pub mod queue_envelope;
\ No newline at end of file
@@ -0,0 +1,51 @@
+use crate::models::input_message::PredictType;
+use serde::{Deserialize, Serialize};
+
+/// Result of a recognition task, written by workers to the outbox queue and published to the output topic.
+/// Times are in milliseconds since the Unix epoch.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OutputMessage {
+    pub id: String,
+    pub status: TaskStatus,
+    pub predict_type: PredictType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_message: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<u128>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<u128>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub published_at: Option<u128>,
+}
+
+impl OutputMessage {
+    /// Result of a task that was not processed, with the error details.
+    pub fn with_error(id: String, predict_type: PredictType, status: TaskStatus, code: &str, message: String) -> Self {
+        OutputMessage {
+            id,
+            status,
+            predict_type,
+            result_path: None,
+            error_code: Some(code.to_string()),
+            error_message: Some(message),
+            started_at: None,
+            finished_at: None,
+            published_at: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum TaskStatus {
+    /// The task is processed, the result is at `result_path`.
+    Success,
+    /// The task failed during processing.
+    Failed,
+    /// The task was not accepted for processing (e.g. the image does not exist).
+    Rejected,
+}
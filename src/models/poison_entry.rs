@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Raw queue entry that could not be deserialized, with the reason it was rejected.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PoisonEntry {
+    pub source_queue: String,
+    pub payload: String,
+    pub error: String,
+    pub poisoned_at: u128,
+}
+
+impl PoisonEntry {
+    pub fn new(source_queue: &str, payload: &str, error: &str) -> Result<Self, std::time::SystemTimeError> {
+        Ok(PoisonEntry {
+            source_queue: source_queue.to_string(),
+            payload: payload.to_string(),
+            error: error.to_string(),
+            poisoned_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis(),
+        })
+    }
+}
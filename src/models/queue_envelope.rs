@@ -0,0 +1,227 @@
+use crate::models::message_header::MessageHeader;
+use crate::models::message_source::MessageSource;
+use crate::models::output_message::OutputMessage;
+use crate::telemetry::propagation::{context_from_map, continue_trace};
+use serde::de::{DeserializeOwned, Error as _};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::Span;
+use tracing::field::Empty;
+
+/// Schema version of queue entries written by the service.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Queue entry: the message (`payload`) plus delivery metadata.
+/// Entries are written in the current schema version, and legacy bare messages are read as well (see `from_json`).
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct QueueEnvelope<M> {
+    pub schema_version: u32,
+    #[serde(rename = "payload")]
+    pub message: M,
+    /// Time (in milliseconds since the Unix epoch) the entry was last pushed to a queue.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enqueued_at: Option<u128>,
+    #[serde(default)]
+    pub attempts: u32,
+    /// Time (in milliseconds since the Unix epoch) before which the entry is not published again after a failed attempt.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_before: Option<u128>,
+    /// Kafka record the message was consumed from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<MessageSource>,
+    /// Headers of the source Kafka record, propagated to the output record.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub headers: Vec<MessageHeader>,
+    /// Time (in milliseconds since the Unix epoch) the message was consumed from Kafka.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub consumed_at: Option<u128>,
+    /// W3C trace context (`traceparent`, `tracestate`) of the consumption span.
+    /// Workers should copy it from the inbox entry to the outbox entry, so the trace continues on publishing.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub trace_context: HashMap<String, String>,
+}
+
+impl<M> QueueEnvelope<M> {
+    pub fn new(message: M) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            message,
+            enqueued_at: None,
+            attempts: 0,
+            not_before: None,
+            source: None,
+            headers: Vec::new(),
+            consumed_at: now_millis(),
+            trace_context: HashMap::new(),
+        }
+    }
+
+    /// Entry written before the envelope was versioned: the bare message without delivery metadata.
+    fn from_legacy(message: M) -> Self {
+        Self {
+            consumed_at: None,
+            ..Self::new(message)
+        }
+    }
+
+    pub fn with_headers(mut self, headers: Vec<MessageHeader>) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    pub fn with_source(mut self, source: MessageSource) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    pub fn with_trace_context(mut self, trace_context: HashMap<String, String>) -> Self {
+        self.trace_context = trace_context;
+        self
+    }
+
+    /// Postpones the next attempt by the delay.
+    pub fn defer(&mut self, delay: Duration) {
+        self.not_before = now_millis().map(|now| now + delay.as_millis());
+    }
+
+    /// Whether the next attempt is postponed to a later time.
+    pub fn is_deferred(&self) -> bool {
+        matches!((self.not_before, now_millis()), (Some(not_before), Some(now)) if not_before > now)
+    }
+}
+
+impl<M: Serialize> QueueEnvelope<M> {
+    /// Stamps the enqueue time, and serializes the entry in the current schema version.
+    pub fn into_json(mut self) -> Result<String, serde_json::Error> {
+        self.schema_version = SCHEMA_VERSION;
+        self.enqueued_at = now_millis();
+
+        serde_json::to_string(&self)
+    }
+}
+
+impl<M: DeserializeOwned> QueueEnvelope<M> {
+    /// Parses an entry: a versioned one if it has `schema_version`, otherwise a legacy bare message.
+    /// Entries of a newer schema version are rejected.
+    pub fn from_json(data: &str) -> Result<Self, serde_json::Error> {
+        let value: serde_json::Value = serde_json::from_str(data)?;
+
+        match value.get("schema_version").and_then(serde_json::Value::as_u64) {
+            Some(version) if version > u64::from(SCHEMA_VERSION) => Err(serde_json::Error::custom(format!(
+                "unsupported schema version {version}, supported up to {SCHEMA_VERSION}"
+            ))),
+            Some(_) => serde_json::from_value(value),
+            None => serde_json::from_value(value).map(QueueEnvelope::from_legacy),
+        }
+    }
+}
+
+impl QueueEnvelope<OutputMessage> {
+    /// Span of the message, continuing the trace of the consumed record.
+    pub fn span(&self) -> Span {
+        let span = tracing::info_span!(
+            "message",
+            id = self.message.id.as_str(),
+            predict_type = self.message.predict_type.as_str(),
+            trace_id = Empty,
+        );
+        continue_trace(&span, context_from_map(&self.trace_context));
+
+        span
+    }
+}
+
+fn now_millis() -> Option<u128> {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::input_message::{InputMessage, PredictType};
+    use crate::models::output_message::TaskStatus;
+    use serde_json::json;
+
+    fn input_message() -> InputMessage {
+        InputMessage {
+            id: "1".to_string(),
+            image_path: "1/1.png".to_string(),
+            result_path: "results/1.json".to_string(),
+            predict_type: PredictType::TypeOne,
+        }
+    }
+
+    #[test]
+    fn reads_versioned_entry() {
+        let envelope = QueueEnvelope::new(input_message())
+            .with_headers(vec![MessageHeader {
+                key: "correlation-id".to_string(),
+                value: Some("abc".to_string()),
+            }])
+            .with_source(MessageSource {
+                topic: "tasks".to_string(),
+                partition: 2,
+                offset: 42,
+            });
+
+        let data = envelope.into_json().unwrap();
+        let parsed = QueueEnvelope::<InputMessage>::from_json(&data).unwrap();
+
+        assert_eq!(parsed.schema_version, SCHEMA_VERSION);
+        assert_eq!(parsed.message.id, "1");
+        assert_eq!(parsed.message.image_path, "1/1.png");
+        assert!(parsed.enqueued_at.is_some());
+        assert!(parsed.consumed_at.is_some());
+        assert_eq!(parsed.headers.len(), 1);
+        assert_eq!(parsed.headers[0].value.as_deref(), Some("abc"));
+
+        let source = parsed.source.unwrap();
+        assert_eq!((source.topic.as_str(), source.partition, source.offset), ("tasks", 2, 42));
+    }
+
+    #[test]
+    fn reads_legacy_bare_input_message() {
+        let data = serde_json::to_string(&input_message()).unwrap();
+
+        let parsed = QueueEnvelope::<InputMessage>::from_json(&data).unwrap();
+
+        assert_eq!(parsed.schema_version, SCHEMA_VERSION);
+        assert_eq!(parsed.message.id, "1");
+        assert_eq!(parsed.attempts, 0);
+        assert!(parsed.headers.is_empty());
+        assert_eq!(parsed.consumed_at, None);
+        assert!(parsed.source.is_none());
+    }
+
+    #[test]
+    fn reads_legacy_bare_output_message() {
+        let data = json!({
+            "id": "2",
+            "status": "SUCCESS",
+            "predict_type": "TYPE_TWO",
+            "result_path": "results/2.json",
+        })
+        .to_string();
+
+        let parsed = QueueEnvelope::<OutputMessage>::from_json(&data).unwrap();
+
+        assert_eq!(parsed.message.id, "2");
+        assert_eq!(parsed.message.status, TaskStatus::Success);
+        assert_eq!(parsed.message.result_path.as_deref(), Some("results/2.json"));
+        assert_eq!(parsed.attempts, 0);
+    }
+
+    #[test]
+    fn rejects_newer_schema_version() {
+        let mut data = serde_json::to_value(QueueEnvelope::new(input_message())).unwrap();
+        data["schema_version"] = json!(SCHEMA_VERSION + 1);
+
+        let err = QueueEnvelope::<InputMessage>::from_json(&data.to_string()).unwrap_err();
+
+        assert!(err.to_string().contains("unsupported schema version 2"));
+    }
+}
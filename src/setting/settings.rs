@@ -1,13 +1,33 @@
-use anyhow::Result;
+use anyhow::{Result, anyhow, bail};
 use config::{Config, Environment};
 use rdkafka::ClientConfig;
 use serde::{Deserialize, Serialize};
 use serde_json::to_string_pretty;
+use std::collections::HashMap;
 use std::path::Path;
+use std::time::Duration;
 use crate::utils::secret::Secret;
 
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
 pub struct S3Config {
+    pub outer: S3StorageConfig,
+    pub inner: S3StorageConfig,
+    #[serde(default = "default_transfer_max_attempts")]
+    pub transfer_max_attempts: u32,
+    #[serde(default = "default_transfer_retry_delay_ms")]
+    pub transfer_retry_delay_ms: u64,
+}
+
+fn default_transfer_max_attempts() -> u32 {
+    3
+}
+
+fn default_transfer_retry_delay_ms() -> u64 {
+    1_000
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct S3StorageConfig {
     pub url: String,
     pub bucket: String,
     pub access_key: Option<Secret>,
@@ -24,6 +44,10 @@ pub struct RedisConfig {
     pub poll_delay_ms: u64,
     pub read_delay_ms: u64,
     pub queues: RedisQueues,
+    #[serde(default)]
+    pub queue_type: RedisQueueType,
+    #[serde(default)]
+    pub stream: RedisStreamConfig,
 }
 
 impl RedisConfig {
@@ -47,54 +71,529 @@ impl RedisConfig {
 pub struct RedisQueues {
     pub inbox: String,
     pub outbox: String,
+    #[serde(default = "default_pending_queue")]
+    pub pending: String,
+    #[serde(default = "default_poison_queue")]
+    pub poison: String,
+    #[serde(default = "default_dead_letter_queue")]
+    pub dead_letter: String,
+}
+
+fn default_pending_queue() -> String {
+    "pending_tasks".to_string()
+}
+
+fn default_poison_queue() -> String {
+    "poison_queue".to_string()
+}
+
+fn default_dead_letter_queue() -> String {
+    "dead_letter_queue".to_string()
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RedisQueueType {
+    #[default]
+    List,
+    Stream,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RedisStreamConfig {
+    pub group: String,
+    /// Consumer name of this instance in the group. The host name (pod name) is used if not set.
+    pub consumer: Option<String>,
+    pub claim_idle_ms: u64,
+    pub claim_interval_ms: u64,
+}
+
+impl Default for RedisStreamConfig {
+    fn default() -> Self {
+        RedisStreamConfig {
+            group: "guelderose".to_string(),
+            consumer: None,
+            claim_idle_ms: 60_000,
+            claim_interval_ms: 10_000,
+        }
+    }
+}
+
+impl RedisStreamConfig {
+    /// Consumer name, unique for every instance, so pending entries show which instance holds them.
+    pub fn consumer_name(&self) -> String {
+        if let Some(consumer) = &self.consumer {
+            return consumer.clone();
+        }
+
+        std::env::var("HOSTNAME")
+            .ok()
+            .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+            .map(|hostname| hostname.trim().to_string())
+            .filter(|hostname| !hostname.is_empty())
+            .unwrap_or_else(|| format!("guelderose-{}", std::process::id()))
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
 pub struct KafkaConfig {
     pub group_id: String,
     pub batch_size: usize,
+    #[serde(default = "default_batch_max_wait_ms")]
+    pub batch_max_wait_ms: u64,
+    #[serde(default = "default_batch_timeout_ms")]
+    pub batch_timeout_ms: u64,
     pub bootstrap_servers: Vec<String>,
     pub auth: Option<KafkaAuthConfig>,
     pub topics: KafkaTopics,
+    #[serde(default)]
+    pub commit_mode: KafkaCommitMode,
+    #[serde(default)]
+    pub assignment_strategy: AssignmentStrategy,
+    #[serde(default)]
+    pub auto_offset_reset: AutoOffsetReset,
+    #[serde(default)]
+    pub enable_auto_commit: bool,
+    #[serde(default)]
+    pub consumer_properties: HashMap<String, String>,
+    #[serde(default)]
+    pub producer_properties: HashMap<String, String>,
+    #[serde(default)]
+    pub producer: ProducerConfig,
+    #[serde(default)]
+    pub publish_retry: PublishRetryConfig,
+    #[serde(default)]
+    pub consume_backoff: BackoffConfig,
+}
+
+fn default_batch_max_wait_ms() -> u64 {
+    5_000
 }
 
+fn default_batch_timeout_ms() -> u64 {
+    240_000
+}
+
+const MAX_POLL_INTERVAL_PROPERTY: &str = "max.poll.interval.ms";
+
+/// Default of `max.poll.interval.ms` in `librdkafka`.
+const DEFAULT_MAX_POLL_INTERVAL_MS: u64 = 300_000;
+
+/// Consumer properties set by dedicated fields of `KafkaConfig`, which cannot be overridden by `consumer_properties`.
+const PROTECTED_CONSUMER_PROPERTIES: [&str; 5] = [
+    "group.id",
+    "bootstrap.servers",
+    "partition.assignment.strategy",
+    "auto.offset.reset",
+    "enable.auto.commit",
+];
+
+/// Producer properties set by dedicated fields of `KafkaConfig`, which cannot be overridden by `producer_properties`.
+const PROTECTED_PRODUCER_PROPERTIES: [&str; 3] = ["bootstrap.servers", "enable.idempotence", "transactional.id"];
+
+/// Security properties set by `KafkaAuthConfig`, which cannot be overridden by consumer or producer properties.
+const PROTECTED_AUTH_PROPERTIES: [&str; 8] = [
+    "security.protocol",
+    "sasl.mechanism",
+    "sasl.username",
+    "sasl.password",
+    "ssl.ca.location",
+    "ssl.certificate.location",
+    "ssl.key.location",
+    "ssl.key.password",
+];
+
 impl KafkaConfig {
     pub fn build_kafka_config(&self) -> ClientConfig {
         let mut config = ClientConfig::new();
 
         config
             .set("group.id", &self.group_id)
-            .set("bootstrap.servers", self.bootstrap_servers.join(","));
+            .set("bootstrap.servers", self.bootstrap_servers.join(","))
+            .set("partition.assignment.strategy", self.assignment_strategy.as_str())
+            .set("auto.offset.reset", self.auto_offset_reset.as_str())
+            .set("enable.auto.commit", self.enable_auto_commit.to_string());
+
+        if let Some(auth_config) = &self.auth {
+            auth_config.apply(&mut config);
+        }
+
+        for (key, value) in &self.consumer_properties {
+            config.set(key, value);
+        }
+
+        config
+    }
+
+    pub fn build_producer_config(&self) -> ClientConfig {
+        let mut config = ClientConfig::new();
+
+        config.set("bootstrap.servers", self.bootstrap_servers.join(","));
+
+        // Left unset unless enabled, since `librdkafka` enables it implicitly for `transactional.id`,
+        // and rejects the transactional producer with an explicit `false`.
+        if self.producer.idempotence {
+            config.set("enable.idempotence", "true");
+        }
 
         if let Some(auth_config) = &self.auth {
-            config
-                .set("security.protocol", &auth_config.protocol)
-                .set("sasl.mechanism", &auth_config.mechanism)
-                .set("sasl.username", auth_config.username.clone().unwrap().reveal())
-                .set("sasl.password", auth_config.password.clone().unwrap().reveal());
+            auth_config.apply(&mut config);
+        }
+
+        for (key, value) in &self.producer_properties {
+            config.set(key, value);
         }
 
         config
     }
+
+    /// Checks that additional properties do not override dedicated fields, and are accepted by `librdkafka`,
+    /// and that the batch timeout with the backoff after a failed batch fits into the poll interval of the consumer.
+    pub fn validate(&self) -> Result<()> {
+        for (properties, protected, client) in [
+            (&self.consumer_properties, PROTECTED_CONSUMER_PROPERTIES.as_slice(), "consumer"),
+            (&self.producer_properties, PROTECTED_PRODUCER_PROPERTIES.as_slice(), "producer"),
+        ] {
+            if let Some(key) = properties
+                .keys()
+                .find(|key| protected.contains(&key.as_str()) || PROTECTED_AUTH_PROPERTIES.contains(&key.as_str()))
+            {
+                bail!("Kafka {client} property [{key}] is set by a dedicated field and cannot be overridden");
+            }
+        }
+
+        self.build_kafka_config()
+            .create_native_config()
+            .map_err(|err| anyhow!("Invalid Kafka consumer properties: {err}"))?;
+        self.build_producer_config()
+            .create_native_config()
+            .map_err(|err| anyhow!("Invalid Kafka producer properties: {err}"))?;
+
+        // The stream is not polled while the tasks of a batch are awaited,
+        // so a longer wait gets the consumer evicted from the group, and the commit of the batch fails.
+        let max_poll_interval_ms = match self.consumer_properties.get(MAX_POLL_INTERVAL_PROPERTY) {
+            Some(value) => value
+                .parse::<u64>()
+                .map_err(|err| anyhow!("Invalid Kafka consumer property [{MAX_POLL_INTERVAL_PROPERTY}]: {err}"))?,
+            None => DEFAULT_MAX_POLL_INTERVAL_MS,
+        };
+
+        // The backoff after a failed batch is awaited without polling as well.
+        if self.batch_timeout_ms.saturating_add(self.consume_backoff.max_delay_ms) >= max_poll_interval_ms {
+            bail!(
+                "batch_timeout_ms ({}) plus consume_backoff.max_delay_ms ({}) must be less than {MAX_POLL_INTERVAL_PROPERTY} ({max_poll_interval_ms})",
+                self.batch_timeout_ms,
+                self.consume_backoff.max_delay_ms
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KafkaCommitMode {
+    Sync,
+    #[default]
+    Async,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AssignmentStrategy {
+    #[default]
+    Range,
+    RoundRobin,
+    CooperativeSticky,
+}
+
+impl AssignmentStrategy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AssignmentStrategy::Range => "range",
+            AssignmentStrategy::RoundRobin => "roundrobin",
+            AssignmentStrategy::CooperativeSticky => "cooperative-sticky",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AutoOffsetReset {
+    Earliest,
+    #[default]
+    Latest,
+    Error,
+}
+
+impl AutoOffsetReset {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AutoOffsetReset::Earliest => "earliest",
+            AutoOffsetReset::Latest => "latest",
+            AutoOffsetReset::Error => "error",
+        }
+    }
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ProducerConfig {
+    pub key_field: MessageKeyField,
+    pub idempotence: bool,
+    pub send_timeout_ms: u64,
+    pub transactional_id: Option<String>,
+    pub transaction_batch_size: usize,
+    pub transaction_timeout_ms: u64,
+}
+
+impl Default for ProducerConfig {
+    fn default() -> Self {
+        ProducerConfig {
+            key_field: MessageKeyField::Id,
+            idempotence: false,
+            send_timeout_ms: 5_000,
+            transactional_id: None,
+            transaction_batch_size: 100,
+            transaction_timeout_ms: 10_000,
+        }
+    }
+}
+
+/// Field of the message used as the key of the output record.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageKeyField {
+    #[default]
+    Id,
+    ResultPath,
+    PredictType,
+    None,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PublishRetryConfig {
+    pub max_attempts: u32,
+    #[serde(flatten)]
+    pub backoff: BackoffConfig,
+}
+
+impl Default for PublishRetryConfig {
+    fn default() -> Self {
+        PublishRetryConfig {
+            max_attempts: 10,
+            backoff: BackoffConfig {
+                base_delay_ms: 500,
+                max_delay_ms: 60_000,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BackoffConfig {
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+
+impl Default for BackoffConfig {
+    fn default() -> Self {
+        BackoffConfig {
+            base_delay_ms: 500,
+            max_delay_ms: 30_000,
+        }
+    }
+}
+
+impl BackoffConfig {
+    /// Exponential backoff with jitter: a random delay between half and the full exponential delay.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponential_delay = 2u64
+            .saturating_pow(attempt.saturating_sub(1))
+            .saturating_mul(self.base_delay_ms)
+            .min(self.max_delay_ms);
+
+        Duration::from_millis(rand::random_range(exponential_delay / 2..=exponential_delay))
+    }
+}
+
+/// Security settings shared by the consumer and the producer.
+/// `protocol` is one of `PLAINTEXT`, `SSL`, `SASL_PLAINTEXT`, `SASL_SSL`,
+/// `mechanism` is one of `PLAIN`, `SCRAM-SHA-256`, `SCRAM-SHA-512` (not needed without SASL).
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct KafkaAuthConfig {
     username: Option<Secret>,
     password: Option<Secret>,
     protocol: String,
-    mechanism: String,
+    mechanism: Option<String>,
+    ca_location: Option<String>,
+    certificate_location: Option<String>,
+    key_location: Option<String>,
+    key_password: Option<Secret>,
+}
+
+impl KafkaAuthConfig {
+    fn apply(&self, config: &mut ClientConfig) {
+        config.set("security.protocol", &self.protocol);
+
+        let properties = [
+            ("sasl.mechanism", self.mechanism.as_deref()),
+            ("sasl.username", self.username.as_ref().map(Secret::reveal)),
+            ("sasl.password", self.password.as_ref().map(Secret::reveal)),
+            ("ssl.ca.location", self.ca_location.as_deref()),
+            ("ssl.certificate.location", self.certificate_location.as_deref()),
+            ("ssl.key.location", self.key_location.as_deref()),
+            ("ssl.key.password", self.key_password.as_ref().map(Secret::reveal)),
+        ];
+
+        for (key, value) in properties {
+            if let Some(value) = value {
+                config.set(key, value);
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
 pub struct KafkaTopics {
     pub input: String,
     pub output: String,
+    pub dlq: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueBackend {
+    #[default]
+    Redis,
+    Memory,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageBackend {
+    #[default]
+    S3,
+    Memory,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct BackendConfig {
+    #[serde(default)]
+    pub queue: QueueBackend,
+    #[serde(default)]
+    pub storage: StorageBackend,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ShutdownConfig {
+    pub timeout_ms: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        ShutdownConfig { timeout_ms: 30_000 }
+    }
+}
+
+impl ShutdownConfig {
+    /// Share of the timeout for finishing the work in progress: awaiting the tasks of the batch, flushing the producer.
+    /// The rest is left for committing offsets and stopping the other tasks.
+    pub fn drain_timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms / 2)
+    }
+
+    /// Checks that the tasks of the batch in progress are checked at least once more on shutdown.
+    pub fn validate(&self, redis_config: &RedisConfig) -> Result<()> {
+        if self.drain_timeout() < Duration::from_millis(redis_config.poll_delay_ms) {
+            bail!(
+                "shutdown.timeout_ms ({}) must be at least twice redis.poll_delay_ms ({})",
+                self.timeout_ms,
+                redis_config.poll_delay_ms
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    pub enabled: bool,
+    pub address: String,
+    pub readiness_timeout_ms: u64,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            enabled: true,
+            address: "0.0.0.0:9090".to_string(),
+            readiness_timeout_ms: 3_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    pub queue_sample_interval_ms: u64,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        MetricsConfig {
+            queue_sample_interval_ms: 5_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    pub exporter: TraceExporter,
+    pub otlp_endpoint: String,
+    pub service_name: String,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        TelemetryConfig {
+            exporter: TraceExporter::None,
+            otlp_endpoint: "http://127.0.0.1:4318/v1/traces".to_string(),
+            service_name: "guelderose".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceExporter {
+    #[default]
+    None,
+    Stdout,
+    Otlp,
 }
 
 #[derive(Debug, Deserialize, Serialize, Default)]
 pub struct Logging {
     pub log_level: String,
+    #[serde(default)]
+    pub format: LogFormat,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
 }
 
 #[derive(Debug, Deserialize, Serialize, Default)]
@@ -103,6 +602,16 @@ pub struct Settings {
     pub redis: RedisConfig,
     pub kafka: KafkaConfig,
     pub logging: Logging,
+    #[serde(default)]
+    pub backend: BackendConfig,
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+    #[serde(default)]
+    pub http: HttpConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
 }
 
 impl Settings {
@@ -112,7 +621,7 @@ impl Settings {
         if Path::new(location).exists() {
             builder = builder.add_source(config::File::with_name(location));
         } else {
-            log::warn!("Configuration file not found");
+            tracing::warn!("Configuration file not found");
         }
 
         builder = builder.add_source(
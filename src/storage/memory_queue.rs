@@ -0,0 +1,118 @@
+use crate::models::poison_entry::PoisonEntry;
+use crate::models::queue_envelope::QueueEnvelope;
+use crate::storage::queue::{Delivery, Queue, QueueMessage};
+use anyhow::Error;
+use async_trait::async_trait;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+/// Queue kept in process memory. Intended for local runs and tests without Redis.
+/// Messages are stored serialized, the same way as in Redis,
+/// and entries that cannot be parsed are moved to the poison queue (kept in memory as well).
+pub struct MemoryQueue {
+    queues: Mutex<HashMap<String, VecDeque<String>>>,
+    poison_key: String,
+}
+
+impl MemoryQueue {
+    pub fn new(poison_key: &str) -> Self {
+        Self {
+            queues: Mutex::new(HashMap::new()),
+            poison_key: poison_key.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl<M: QueueMessage> Queue<M> for MemoryQueue {
+    async fn push(&self, queue_key: &str, envelope: QueueEnvelope<M>) -> Result<(), Error> {
+        let serialized_message = envelope.into_json()?;
+
+        let mut queues = self.queues.lock().expect("poisoned lock");
+        queues
+            .entry(queue_key.to_string())
+            .or_default()
+            .push_back(serialized_message);
+
+        Ok(())
+    }
+
+    async fn pop(&self, queue_key: &str) -> Result<Option<Delivery<M>>, Error> {
+        let mut queues = self.queues.lock().expect("poisoned lock");
+
+        while let Some(serialized_message) = queues.get_mut(queue_key).and_then(VecDeque::pop_front) {
+            match QueueEnvelope::<M>::from_json(&serialized_message) {
+                Ok(envelope) => {
+                    return Ok(Some(Delivery {
+                        envelope,
+                        receipt: None,
+                    }));
+                }
+                Err(err) => {
+                    let entry = PoisonEntry::new(queue_key, &serialized_message, &err.to_string())?;
+
+                    queues
+                        .entry(self.poison_key.clone())
+                        .or_default()
+                        .push_back(serde_json::to_string(&entry)?);
+                    tracing::error!(
+                        "Failed to deserialize message from queue [{queue_key}]: {err}. Message moved to poison queue: [{}]",
+                        self.poison_key
+                    );
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    async fn ack(&self, _queue_key: &str, _delivery: &Delivery<M>) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn nack(&self, queue_key: &str, delivery: Delivery<M>) -> Result<(), Error> {
+        self.push(queue_key, delivery.envelope).await
+    }
+
+    async fn len(&self, queue_key: &str) -> Result<usize, Error> {
+        let queues = self.queues.lock().expect("poisoned lock");
+
+        Ok(queues.get(queue_key).map_or(0, VecDeque::len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::input_message::{InputMessage, PredictType};
+
+    #[tokio::test]
+    async fn moves_unparseable_entries_to_poison_queue() {
+        let queue = MemoryQueue::new("poison");
+        queue
+            .queues
+            .lock()
+            .expect("poisoned lock")
+            .entry("inbox".to_string())
+            .or_default()
+            .push_back("not a task".to_string());
+
+        let task = InputMessage {
+            id: "1".to_string(),
+            image_path: "1/1.png".to_string(),
+            result_path: "results/1.json".to_string(),
+            predict_type: PredictType::TypeOne,
+        };
+        queue.push("inbox", QueueEnvelope::new(task)).await.unwrap();
+
+        let delivery: Delivery<InputMessage> = queue.pop("inbox").await.unwrap().unwrap();
+        assert_eq!(delivery.envelope.message.id, "1");
+
+        let poison = queue.queues.lock().expect("poisoned lock")["poison"].clone();
+        assert_eq!(poison.len(), 1);
+
+        let entry: PoisonEntry = serde_json::from_str(&poison[0]).unwrap();
+        assert_eq!(entry.source_queue, "inbox");
+        assert_eq!(entry.payload, "not a task");
+    }
+}
@@ -0,0 +1,45 @@
+use crate::storage::object_store::ObjectStore;
+use anyhow::Error;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Object store kept in process memory. Intended for local runs and tests without S3.
+pub struct MemoryStorage {
+    bucket: String,
+    objects: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl MemoryStorage {
+    pub fn new(bucket: &str) -> Self {
+        Self {
+            bucket: bucket.to_string(),
+            objects: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl ObjectStore for MemoryStorage {
+    fn bucket(&self) -> &str {
+        self.bucket.as_str()
+    }
+
+    async fn get_object(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        let objects = self.objects.lock().expect("poisoned lock");
+
+        Ok(objects.get(key).cloned())
+    }
+
+    async fn put_object(&self, key: &str, data: Vec<u8>) -> Result<(), Error> {
+        let mut objects = self.objects.lock().expect("poisoned lock");
+
+        objects.insert(key.to_string(), data);
+
+        Ok(())
+    }
+
+    async fn check(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,43 @@
+use crate::storage::task_tracker::TaskTracker;
+use anyhow::Error;
+use async_trait::async_trait;
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+/// Task tracker kept in process memory. Intended for local runs and tests without Redis.
+#[derive(Default)]
+pub struct MemoryTaskTracker {
+    pending: Mutex<HashSet<String>>,
+}
+
+impl MemoryTaskTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl TaskTracker for MemoryTaskTracker {
+    async fn track(&self, task_ids: &[String]) -> Result<(), Error> {
+        let mut pending = self.pending.lock().expect("poisoned lock");
+        pending.extend(task_ids.iter().cloned());
+
+        Ok(())
+    }
+
+    async fn complete(&self, task_ids: &[String]) -> Result<(), Error> {
+        let mut pending = self.pending.lock().expect("poisoned lock");
+
+        for task_id in task_ids {
+            pending.remove(task_id);
+        }
+
+        Ok(())
+    }
+
+    async fn pending(&self, task_ids: &[String]) -> Result<usize, Error> {
+        let pending = self.pending.lock().expect("poisoned lock");
+
+        Ok(task_ids.iter().filter(|task_id| pending.contains(*task_id)).count())
+    }
+}
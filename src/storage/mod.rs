@@ -1,2 +1,11 @@
+pub mod memory_queue;
+pub mod memory_storage;
+pub mod memory_task_tracker;
+pub mod object_store;
+pub mod poison_queue;
+pub mod queue;
 pub mod redis_queue;
+pub mod redis_stream_queue;
+pub mod redis_task_tracker;
 pub mod s3_storage;
+pub mod task_tracker;
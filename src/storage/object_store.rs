@@ -0,0 +1,46 @@
+use anyhow::Error;
+use async_trait::async_trait;
+
+#[async_trait]
+pub trait ObjectStore: Send + Sync {
+    fn bucket(&self) -> &str;
+
+    /// Returns `None` if the object does not exist.
+    async fn get_object(&self, key: &str) -> Result<Option<Vec<u8>>, Error>;
+
+    async fn put_object(&self, key: &str, data: Vec<u8>) -> Result<(), Error>;
+
+    /// Checks that the bucket exists and is accessible.
+    async fn check(&self) -> Result<(), Error>;
+}
+
+/// Copies an object from the source storage to the target one.
+/// Returns `false` if the source object does not exist.
+pub async fn transfer(
+    source: &dyn ObjectStore,
+    key: &str,
+    target: &dyn ObjectStore,
+    target_key: &str,
+) -> Result<bool, Error> {
+    let Some(data) = source.get_object(key).await? else {
+        return Ok(false);
+    };
+
+    target.put_object(target_key, data).await?;
+    tracing::debug!(
+        "Successfully transferred {}/{} to {}/{}",
+        source.bucket(),
+        key,
+        target.bucket(),
+        target_key
+    );
+
+    Ok(true)
+}
+
+/// Builds the object key of a task file: `<task_id>/<file name>`.
+pub fn build_task_key(task_id: &str, path: &str) -> String {
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+
+    format!("{task_id}/{file_name}")
+}
@@ -0,0 +1,130 @@
+use crate::models::poison_entry::PoisonEntry;
+use crate::models::queue_envelope::QueueEnvelope;
+use crate::storage::queue::{Queue, QueueMessage};
+use anyhow::{Error, anyhow};
+use redis::aio::MultiplexedConnection;
+use redis::{AsyncCommands, Script};
+
+/// Moves the head of the source list (if it is still the given entry) to the poison queue in one step.
+const MOVE_HEAD_SCRIPT: &str = r"
+if redis.call('LINDEX', KEYS[1], 0) == ARGV[1] then
+    redis.call('LPOP', KEYS[1])
+    redis.call('RPUSH', KEYS[2], ARGV[2])
+    return 1
+end
+return 0
+";
+
+/// Redis list holding raw queue entries that failed deserialization on pop.
+pub struct PoisonQueue {
+    connection: MultiplexedConnection,
+    queue_key: String,
+    move_head_script: Script,
+}
+
+impl PoisonQueue {
+    pub fn new(connection: MultiplexedConnection, queue_key: String) -> Self {
+        Self {
+            connection,
+            queue_key,
+            move_head_script: Script::new(MOVE_HEAD_SCRIPT),
+        }
+    }
+
+    pub async fn push(&self, source_queue: &str, payload: &str, error: &str) -> Result<(), Error> {
+        let mut connection = self.connection.clone();
+
+        let serialized_entry = serde_json::to_string(&PoisonEntry::new(source_queue, payload, error)?)?;
+
+        let _: i64 = connection.rpush(&self.queue_key, serialized_entry).await?;
+        self.log_moved(source_queue, error);
+
+        Ok(())
+    }
+
+    /// Moves the entry at the head of the source list to the poison queue atomically, so it cannot be lost in between.
+    /// Returns `false` if the entry is no longer at the head (e.g. it has been read by another instance).
+    pub async fn move_head(&self, source_queue: &str, payload: &str, error: &str) -> Result<bool, Error> {
+        let mut connection = self.connection.clone();
+
+        let serialized_entry = serde_json::to_string(&PoisonEntry::new(source_queue, payload, error)?)?;
+
+        let moved: bool = self
+            .move_head_script
+            .key(source_queue)
+            .key(&self.queue_key)
+            .arg(payload)
+            .arg(serialized_entry)
+            .invoke_async(&mut connection)
+            .await?;
+
+        if moved {
+            self.log_moved(source_queue, error);
+        }
+
+        Ok(moved)
+    }
+
+    fn log_moved(&self, source_queue: &str, error: &str) {
+        tracing::error!(
+            "Failed to deserialize message from queue [{source_queue}]: {error}. Message moved to poison queue: [{}]",
+            self.queue_key
+        );
+    }
+
+    pub async fn len(&self) -> Result<usize, Error> {
+        let mut connection = self.connection.clone();
+
+        let len: usize = connection.llen(&self.queue_key).await?;
+
+        Ok(len)
+    }
+
+    pub async fn list(&self, start: isize, count: isize) -> Result<Vec<PoisonEntry>, Error> {
+        let mut connection = self.connection.clone();
+
+        let entries: Vec<String> = connection.lrange(&self.queue_key, start, start + count - 1).await?;
+
+        entries
+            .iter()
+            .map(|entry| serde_json::from_str::<PoisonEntry>(entry).map_err(Error::from))
+            .collect()
+    }
+
+    pub async fn inspect(&self, index: isize) -> Result<Option<PoisonEntry>, Error> {
+        let mut connection = self.connection.clone();
+
+        let entry: Option<String> = connection.lindex(&self.queue_key, index).await?;
+
+        Ok(entry
+            .map(|entry| serde_json::from_str::<PoisonEntry>(&entry))
+            .transpose()?)
+    }
+
+    /// Parses the entry payload again and pushes it back to its source queue.
+    /// The entry is kept in the poison queue if it still cannot be parsed.
+    pub async fn requeue<M: QueueMessage>(&self, index: isize, queue: &dyn Queue<M>) -> Result<PoisonEntry, Error> {
+        let mut connection = self.connection.clone();
+
+        let raw_entry: Option<String> = connection.lindex(&self.queue_key, index).await?;
+        let raw_entry = raw_entry.ok_or_else(|| anyhow!("No entry at index {index} in poison queue"))?;
+        let entry = serde_json::from_str::<PoisonEntry>(&raw_entry)?;
+
+        let envelope = QueueEnvelope::<M>::from_json(&entry.payload)
+            .map_err(|err| anyhow!("Entry at index {index} still cannot be parsed: {err}"))?;
+
+        queue.push(&entry.source_queue, envelope).await?;
+        let _: i64 = connection.lrem(&self.queue_key, 1, raw_entry).await?;
+
+        Ok(entry)
+    }
+
+    pub async fn purge(&self) -> Result<usize, Error> {
+        let len = self.len().await?;
+
+        let mut connection = self.connection.clone();
+        let _: i64 = connection.del(&self.queue_key).await?;
+
+        Ok(len)
+    }
+}
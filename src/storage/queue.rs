@@ -0,0 +1,33 @@
+use crate::models::queue_envelope::QueueEnvelope;
+use anyhow::Error;
+use async_trait::async_trait;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+/// Message that can be stored in a queue.
+pub trait QueueMessage: Serialize + DeserializeOwned + Send + Sync + 'static {}
+
+impl<T: Serialize + DeserializeOwned + Send + Sync + 'static> QueueMessage for T {}
+
+/// Message popped from a queue. Stays owned by the reader until it is acknowledged or returned.
+pub struct Delivery<M> {
+    pub envelope: QueueEnvelope<M>,
+    pub receipt: Option<String>,
+}
+
+#[async_trait]
+pub trait Queue<M: QueueMessage>: Send + Sync {
+    async fn push(&self, queue_key: &str, envelope: QueueEnvelope<M>) -> Result<(), Error>;
+
+    /// Returns `None` if there is nothing to read.
+    async fn pop(&self, queue_key: &str) -> Result<Option<Delivery<M>>, Error>;
+
+    /// Confirms that the delivery has been processed and can be dropped.
+    async fn ack(&self, queue_key: &str, delivery: &Delivery<M>) -> Result<(), Error>;
+
+    /// Returns the delivery to the queue to be processed again.
+    async fn nack(&self, queue_key: &str, delivery: Delivery<M>) -> Result<(), Error>;
+
+    /// Number of messages waiting to be read.
+    async fn len(&self, queue_key: &str) -> Result<usize, Error>;
+}
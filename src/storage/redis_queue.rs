@@ -1,63 +1,99 @@
-use crate::models::input_message::InputMessage;
-use crate::setting::settings::RedisConfig;
+use crate::models::queue_envelope::QueueEnvelope;
+use crate::storage::poison_queue::PoisonQueue;
+use crate::storage::queue::{Delivery, Queue, QueueMessage};
 use anyhow::Error;
-use redis::AsyncCommands;
+use async_trait::async_trait;
 use redis::aio::MultiplexedConnection;
-use std::time::Duration;
+use redis::{AsyncCommands, Script};
+use std::sync::Arc;
+
+/// Pops the head of the list, if it is still the given entry.
+const POP_HEAD_SCRIPT: &str = r"
+if redis.call('LINDEX', KEYS[1], 0) == ARGV[1] then
+    redis.call('LPOP', KEYS[1])
+    return 1
+end
+return 0
+";
 
 pub struct RedisQueue {
     connection: MultiplexedConnection,
-    redis_config: RedisConfig,
+    poison_queue: Arc<PoisonQueue>,
+    pop_head_script: Script,
 }
 
 impl RedisQueue {
-    pub fn new(connection: MultiplexedConnection, redis_config: RedisConfig) -> Self {
+    pub fn new(connection: MultiplexedConnection, poison_queue: Arc<PoisonQueue>) -> Self {
         Self {
             connection,
-            redis_config,
+            poison_queue,
+            pop_head_script: Script::new(POP_HEAD_SCRIPT),
         }
     }
+}
 
-    pub async fn push(&self, queue_key: &str, message: InputMessage) -> Result<(), Error> {
+#[async_trait]
+impl<M: QueueMessage> Queue<M> for RedisQueue {
+    async fn push(&self, queue_key: &str, envelope: QueueEnvelope<M>) -> Result<(), Error> {
         let mut connection = self.connection.clone();
 
-        let serialized_message = serde_json::to_vec(&message)?;
+        let serialized_message = envelope.into_json()?;
 
         let _: i64 = connection.rpush(queue_key, serialized_message).await?;
 
         Ok(())
     }
 
-    pub async fn pop(&self, queue_key: &str) -> Result<Option<InputMessage>, Error> {
-        let read_delay = Duration::from_millis(self.redis_config.read_delay_ms);
+    /// Reads the head of the list and removes it only once it is parsed or moved to the poison queue,
+    /// so an entry that cannot be parsed is never lost. The head is re-read if another instance has taken it meanwhile.
+    async fn pop(&self, queue_key: &str) -> Result<Option<Delivery<M>>, Error> {
         let mut connection = self.connection.clone();
 
         loop {
-            let result: Option<String> = connection.lpop(queue_key, None).await?;
-
-            match result {
-                Some(serialized_message) => match serde_json::from_slice::<InputMessage>(serialized_message.as_bytes())
-                {
-                    Ok(message) => return Ok(Some(message)),
-                    Err(err) => log::error!("Failed to deserialize message from queue [{queue_key}]: {err}"),
-                },
-                None => tokio::time::sleep(read_delay).await,
+            let result: Option<String> = connection.lindex(queue_key, 0).await?;
+
+            let Some(serialized_message) = result else {
+                return Ok(None);
+            };
+
+            match QueueEnvelope::<M>::from_json(&serialized_message) {
+                Ok(envelope) => {
+                    let popped: bool = self
+                        .pop_head_script
+                        .key(queue_key)
+                        .arg(&serialized_message)
+                        .invoke_async(&mut connection)
+                        .await?;
+
+                    if popped {
+                        return Ok(Some(Delivery {
+                            envelope,
+                            receipt: None,
+                        }));
+                    }
+                }
+                Err(err) => {
+                    self.poison_queue
+                        .move_head(queue_key, &serialized_message, &err.to_string())
+                        .await?;
+                }
             }
         }
     }
 
-    pub async fn check_queue(&self, queue_key: &str, poll_delay: Duration) -> Result<(), Error> {
-        let mut connection = self.connection.clone();
+    async fn ack(&self, _queue_key: &str, _delivery: &Delivery<M>) -> Result<(), Error> {
+        Ok(())
+    }
 
-        loop {
-            let len: usize = connection.llen(queue_key).await?;
-            log::debug!("Redis queue check: key='{queue_key}', length={len}");
+    async fn nack(&self, queue_key: &str, delivery: Delivery<M>) -> Result<(), Error> {
+        self.push(queue_key, delivery.envelope).await
+    }
 
-            if len == 0 {
-                return Ok(());
-            }
+    async fn len(&self, queue_key: &str) -> Result<usize, Error> {
+        let mut connection = self.connection.clone();
 
-            tokio::time::sleep(poll_delay).await;
-        }
+        let len: usize = connection.llen(queue_key).await?;
+
+        Ok(len)
     }
 }
@@ -0,0 +1,240 @@
+use crate::models::queue_envelope::QueueEnvelope;
+use crate::setting::settings::RedisStreamConfig;
+use crate::storage::poison_queue::PoisonQueue;
+use crate::storage::queue::{Delivery, Queue, QueueMessage};
+use anyhow::Error;
+use async_trait::async_trait;
+use redis::AsyncCommands;
+use redis::aio::MultiplexedConnection;
+use redis::streams::{
+    StreamAutoClaimOptions, StreamAutoClaimReply, StreamId, StreamInfoGroupsReply, StreamRangeReply, StreamReadOptions,
+    StreamReadReply,
+};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const PAYLOAD_FIELD: &str = "payload";
+
+pub struct RedisStreamQueue {
+    connection: MultiplexedConnection,
+    stream_config: RedisStreamConfig,
+    consumer: String,
+    known_groups: Mutex<HashSet<String>>,
+    claimed_at: Mutex<HashMap<String, Instant>>,
+    poison_queue: Arc<PoisonQueue>,
+}
+
+impl RedisStreamQueue {
+    pub fn new(
+        connection: MultiplexedConnection,
+        stream_config: RedisStreamConfig,
+        poison_queue: Arc<PoisonQueue>,
+    ) -> Self {
+        let consumer = stream_config.consumer_name();
+        tracing::info!("Redis stream consumer name: [{consumer}]");
+
+        Self {
+            connection,
+            stream_config,
+            consumer,
+            known_groups: Mutex::new(HashSet::new()),
+            claimed_at: Mutex::new(HashMap::new()),
+            poison_queue,
+        }
+    }
+
+    async fn remove_entry(&self, queue_key: &str, entry_id: &str) -> Result<(), Error> {
+        let mut connection = self.connection.clone();
+
+        let _: usize = connection
+            .xack(queue_key, &self.stream_config.group, &[entry_id])
+            .await?;
+        let _: usize = connection.xdel(queue_key, &[entry_id]).await?;
+
+        Ok(())
+    }
+
+    /// Claims a stale pending entry of another consumer. Runs at most once per `claim_interval_ms`
+    /// while there is nothing to claim, and on every call while stale entries are found.
+    async fn claim_stale(&self, queue_key: &str) -> Result<Option<StreamId>, Error> {
+        let claim_interval = Duration::from_millis(self.stream_config.claim_interval_ms);
+
+        let claim_due = self
+            .claimed_at
+            .lock()
+            .expect("poisoned lock")
+            .get(queue_key)
+            .is_none_or(|claimed_at| claimed_at.elapsed() >= claim_interval);
+
+        if !claim_due {
+            return Ok(None);
+        }
+
+        let mut connection = self.connection.clone();
+
+        let reply: StreamAutoClaimReply = connection
+            .xautoclaim_options(
+                queue_key,
+                &self.stream_config.group,
+                &self.consumer,
+                self.stream_config.claim_idle_ms,
+                "0-0",
+                StreamAutoClaimOptions::default().count(1),
+            )
+            .await?;
+
+        let entry = reply.claimed.into_iter().next();
+
+        match &entry {
+            Some(entry) => tracing::warn!("Claimed stale pending entry [{}] from stream [{queue_key}]", entry.id),
+            None => {
+                self.claimed_at
+                    .lock()
+                    .expect("poisoned lock")
+                    .insert(queue_key.to_string(), Instant::now());
+            }
+        }
+
+        Ok(entry)
+    }
+
+    async fn read_new(&self, queue_key: &str) -> Result<Option<StreamId>, Error> {
+        let mut connection = self.connection.clone();
+
+        let options = StreamReadOptions::default()
+            .group(&self.stream_config.group, &self.consumer)
+            .count(1);
+
+        let reply: Option<StreamReadReply> = connection.xread_options(&[queue_key], &[">"], &options).await?;
+
+        Ok(reply.and_then(|reply| reply.keys.into_iter().flat_map(|key| key.ids).next()))
+    }
+
+    async fn ensure_group(&self, queue_key: &str) -> Result<(), Error> {
+        if self.known_groups.lock().expect("poisoned lock").contains(queue_key) {
+            return Ok(());
+        }
+
+        let mut connection = self.connection.clone();
+
+        let result: redis::RedisResult<()> = connection
+            .xgroup_create_mkstream(queue_key, &self.stream_config.group, "0")
+            .await;
+
+        match result {
+            Ok(()) => tracing::info!("Created consumer group [{}] for stream [{queue_key}]", self.stream_config.group),
+            Err(err) if err.code() == Some("BUSYGROUP") => {}
+            Err(err) => return Err(Error::from(err)),
+        }
+
+        self.known_groups
+            .lock()
+            .expect("poisoned lock")
+            .insert(queue_key.to_string());
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<M: QueueMessage> Queue<M> for RedisStreamQueue {
+    async fn push(&self, queue_key: &str, envelope: QueueEnvelope<M>) -> Result<(), Error> {
+        self.ensure_group(queue_key).await?;
+
+        let mut connection = self.connection.clone();
+
+        let serialized_message = envelope.into_json()?;
+
+        let _: Option<String> = connection
+            .xadd(queue_key, "*", &[(PAYLOAD_FIELD, serialized_message)])
+            .await?;
+
+        Ok(())
+    }
+
+    /// Returns the next entry for the consumer group: stale pending entries of other consumers first (when the claim is due),
+    /// then new ones.
+    /// The returned entry stays pending until it is acknowledged.
+    async fn pop(&self, queue_key: &str) -> Result<Option<Delivery<M>>, Error> {
+        self.ensure_group(queue_key).await?;
+
+        loop {
+            let entry = match self.claim_stale(queue_key).await? {
+                Some(entry) => Some(entry),
+                None => self.read_new(queue_key).await?,
+            };
+
+            let Some(entry) = entry else {
+                return Ok(None);
+            };
+
+            let payload = entry.get::<String>(PAYLOAD_FIELD).unwrap_or_default();
+
+            match QueueEnvelope::<M>::from_json(&payload) {
+                Ok(envelope) => {
+                    return Ok(Some(Delivery {
+                        envelope,
+                        receipt: Some(entry.id),
+                    }));
+                }
+                Err(err) => {
+                    // The entry stays pending until it is in the poison queue, so it is not lost if the push fails.
+                    self.poison_queue.push(queue_key, &payload, &err.to_string()).await?;
+                    self.remove_entry(queue_key, &entry.id).await?;
+                }
+            }
+        }
+    }
+
+    async fn ack(&self, queue_key: &str, delivery: &Delivery<M>) -> Result<(), Error> {
+        match &delivery.receipt {
+            Some(entry_id) => self.remove_entry(queue_key, entry_id).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Re-adds the message to the end of the stream and drops the pending entry.
+    async fn nack(&self, queue_key: &str, delivery: Delivery<M>) -> Result<(), Error> {
+        let Delivery { envelope, receipt } = delivery;
+
+        self.push(queue_key, envelope).await?;
+
+        match receipt {
+            Some(entry_id) => self.remove_entry(queue_key, &entry_id).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Number of entries not yet delivered to the consumer group.
+    async fn len(&self, queue_key: &str) -> Result<usize, Error> {
+        self.ensure_group(queue_key).await?;
+
+        let mut connection = self.connection.clone();
+        let group = self.stream_config.group.as_str();
+
+        let groups: StreamInfoGroupsReply = connection.xinfo_groups(queue_key).await?;
+        let Some(info) = groups.groups.into_iter().find(|info| info.name == group) else {
+            return Ok(0);
+        };
+
+        if let Some(lag) = info.lag {
+            return Ok(lag);
+        }
+
+        // Redis < 7 does not report the lag, so fall back to comparing the last delivered and the last entry ids.
+        let last_entry: StreamRangeReply = connection.xrevrange_count(queue_key, "+", "-", 1).await?;
+        let last_delivered_id = parse_entry_id(&info.last_delivered_id);
+
+        match last_entry.ids.first() {
+            Some(entry) if parse_entry_id(&entry.id) > last_delivered_id => Ok(connection.xlen(queue_key).await?),
+            _ => Ok(0),
+        }
+    }
+}
+
+fn parse_entry_id(entry_id: &str) -> (u64, u64) {
+    let (millis, sequence) = entry_id.split_once('-').unwrap_or((entry_id, "0"));
+
+    (millis.parse().unwrap_or_default(), sequence.parse().unwrap_or_default())
+}
@@ -0,0 +1,53 @@
+use crate::storage::task_tracker::TaskTracker;
+use anyhow::Error;
+use async_trait::async_trait;
+use redis::AsyncCommands;
+use redis::aio::MultiplexedConnection;
+
+/// Keeps ids of pending tasks in a Redis set. Workers may also remove ids themselves with `SREM`.
+pub struct RedisTaskTracker {
+    connection: MultiplexedConnection,
+    set_key: String,
+}
+
+impl RedisTaskTracker {
+    pub fn new(connection: MultiplexedConnection, set_key: String) -> Self {
+        Self { connection, set_key }
+    }
+}
+
+#[async_trait]
+impl TaskTracker for RedisTaskTracker {
+    async fn track(&self, task_ids: &[String]) -> Result<(), Error> {
+        if task_ids.is_empty() {
+            return Ok(());
+        }
+
+        let mut connection = self.connection.clone();
+        let _: usize = connection.sadd(&self.set_key, task_ids).await?;
+
+        Ok(())
+    }
+
+    async fn complete(&self, task_ids: &[String]) -> Result<(), Error> {
+        if task_ids.is_empty() {
+            return Ok(());
+        }
+
+        let mut connection = self.connection.clone();
+        let _: usize = connection.srem(&self.set_key, task_ids).await?;
+
+        Ok(())
+    }
+
+    async fn pending(&self, task_ids: &[String]) -> Result<usize, Error> {
+        if task_ids.is_empty() {
+            return Ok(0);
+        }
+
+        let mut connection = self.connection.clone();
+        let members: Vec<bool> = connection.smismember(&self.set_key, task_ids).await?;
+
+        Ok(members.into_iter().filter(|is_member| *is_member).count())
+    }
+}
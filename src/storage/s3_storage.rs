@@ -1,5 +1,8 @@
-use crate::setting::settings::S3Config;
+use crate::metrics::registry::METRICS;
+use crate::setting::settings::S3StorageConfig;
+use crate::storage::object_store::ObjectStore;
 use anyhow::Error;
+use async_trait::async_trait;
 use aws_config::timeout::TimeoutConfig;
 use aws_config::{BehaviorVersion, ConfigLoader};
 use aws_credential_types::Credentials;
@@ -7,15 +10,15 @@ use aws_sdk_s3::Client;
 use aws_sdk_s3::presigning::PresigningConfig;
 use aws_sdk_s3::primitives::ByteStream;
 use std::path::Path;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 pub struct S3Storage {
     client: Client,
-    config: S3Config,
+    config: S3StorageConfig,
 }
 
 impl S3Storage {
-    pub async fn new(config: S3Config) -> Self {
+    pub async fn new(config: S3StorageConfig) -> Self {
         let access_key = config
             .access_key
             .as_ref()
@@ -47,6 +50,7 @@ impl S3Storage {
         S3Storage { client, config }
     }
 
+    #[allow(dead_code)]
     pub async fn upload_file(&self, key: &str, file_path: &str) -> Result<(), Error> {
         let path = Path::new(file_path);
         let body = ByteStream::from_path(path).await?;
@@ -58,11 +62,12 @@ impl S3Storage {
             .body(body)
             .send()
             .await?;
-        log::debug!("Successfully uploaded file to {}/{}", &self.config.bucket, key);
+        tracing::debug!("Successfully uploaded file to {}/{}", &self.config.bucket, key);
 
         Ok(())
     }
 
+    #[allow(dead_code)]
     pub async fn download_file(&self, key: &str, output_path: &str) -> Result<(), Error> {
         let path = Path::new(output_path);
         let resp = self
@@ -75,21 +80,24 @@ impl S3Storage {
 
         let data = resp.body.collect().await?.into_bytes();
         tokio::fs::write(path, data).await?;
-        log::debug!("Successfully downloaded {}/{} to {}", self.config.bucket, key, output_path);
+        tracing::debug!("Successfully downloaded {}/{} to {}", self.config.bucket, key, output_path);
 
         Ok(())
     }
 
+    #[allow(dead_code)]
     pub async fn put_presigned_url(&self, bucket: &str, key: &str, expire_days: Duration) -> Result<String, Error> {
         self.presigned_url_with_operation(bucket, key, expire_days, S3PreSignOps::PutObject)
             .await
     }
 
+    #[allow(dead_code)]
     pub async fn get_presigned_url(&self, bucket: &str, key: &str, expire_days: Duration) -> Result<String, Error> {
         self.presigned_url_with_operation(bucket, key, expire_days, S3PreSignOps::GetObject)
             .await
     }
 
+    #[allow(dead_code)]
     async fn presigned_url_with_operation(
         &self,
         bucket: &str,
@@ -119,12 +127,77 @@ impl S3Storage {
         };
 
         let presigned_url = presigned_request.uri().to_string();
-        log::debug!("Successfully create url: {}", &presigned_url);
+        tracing::debug!("Successfully create url: {}", &presigned_url);
 
         Ok(presigned_url)
     }
+
+    fn observe(&self, operation: &str, started_at: Instant, bytes: usize) {
+        let labels = [self.config.bucket.as_str(), operation];
+
+        METRICS.storage_bytes.with_label_values(&labels).inc_by(bytes as u64);
+        METRICS
+            .storage_duration
+            .with_label_values(&labels)
+            .observe(started_at.elapsed().as_secs_f64());
+    }
+}
+
+#[async_trait]
+impl ObjectStore for S3Storage {
+    fn bucket(&self) -> &str {
+        self.config.bucket.as_str()
+    }
+
+    async fn get_object(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        let started_at = Instant::now();
+
+        let result = self
+            .client
+            .get_object()
+            .bucket(&self.config.bucket)
+            .key(key)
+            .send()
+            .await;
+
+        let resp = match result {
+            Ok(resp) => resp,
+            Err(err) if err.as_service_error().is_some_and(|err| err.is_no_such_key()) => return Ok(None),
+            Err(err) => return Err(Error::from(err)),
+        };
+
+        let data = resp.body.collect().await?.to_vec();
+        tracing::debug!("Successfully got object {}/{}", self.config.bucket, key);
+        self.observe("get", started_at, data.len());
+
+        Ok(Some(data))
+    }
+
+    async fn put_object(&self, key: &str, data: Vec<u8>) -> Result<(), Error> {
+        let started_at = Instant::now();
+        let data_len = data.len();
+
+        self.client
+            .put_object()
+            .bucket(&self.config.bucket)
+            .key(key)
+            .body(ByteStream::from(data))
+            .send()
+            .await?;
+        tracing::debug!("Successfully put object {}/{}", self.config.bucket, key);
+        self.observe("put", started_at, data_len);
+
+        Ok(())
+    }
+
+    async fn check(&self) -> Result<(), Error> {
+        self.client.head_bucket().bucket(&self.config.bucket).send().await?;
+
+        Ok(())
+    }
 }
 
+#[allow(dead_code)]
 enum S3PreSignOps {
     GetObject,
     PutObject,
@@ -0,0 +1,13 @@
+use anyhow::Error;
+use async_trait::async_trait;
+
+/// Tracks tasks handed over to recognition workers until their results reach the outbox queue.
+#[async_trait]
+pub trait TaskTracker: Send + Sync {
+    async fn track(&self, task_ids: &[String]) -> Result<(), Error>;
+
+    async fn complete(&self, task_ids: &[String]) -> Result<(), Error>;
+
+    /// Number of the given tasks that are not completed yet.
+    async fn pending(&self, task_ids: &[String]) -> Result<usize, Error>;
+}
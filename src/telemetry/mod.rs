@@ -0,0 +1,3 @@
+pub mod propagation;
+pub mod stdout_exporter;
+pub mod tracer;
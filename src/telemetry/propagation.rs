@@ -0,0 +1,58 @@
+use crate::models::message_header::MessageHeader;
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::TraceContextExt;
+use opentelemetry::{Context, global};
+use std::collections::HashMap;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// W3C trace context headers. On the output record they are replaced by the context of the service span.
+pub const TRACE_CONTEXT_HEADERS: [&str; 2] = ["traceparent", "tracestate"];
+
+struct HeaderExtractor<'a>(&'a [MessageHeader]);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|header| header.key.eq_ignore_ascii_case(key))
+            .and_then(|header| header.value.as_deref())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.iter().map(|header| header.key.as_str()).collect()
+    }
+}
+
+/// Extracts the trace context from the headers of a Kafka record.
+pub fn context_from_headers(headers: &[MessageHeader]) -> Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)))
+}
+
+/// Extracts the trace context stored in a queue entry.
+pub fn context_from_map(carrier: &HashMap<String, String>) -> Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(carrier))
+}
+
+/// Serializes the trace context of the span, to be stored in a queue entry or sent in Kafka headers.
+pub fn context_to_map(span: &Span) -> HashMap<String, String> {
+    let mut carrier = HashMap::new();
+    global::get_text_map_propagator(|propagator| propagator.inject_context(&span.context(), &mut carrier));
+
+    carrier
+}
+
+/// Makes the span a child of the extracted context (a new trace is started if the context is empty),
+/// and records the trace id into its `trace_id` field.
+pub fn continue_trace(span: &Span, parent: Context) {
+    if let Err(err) = span.set_parent(parent) {
+        tracing::debug!("Failed to set parent of span: {err}");
+    }
+
+    let context = span.context();
+    let span_context = context.span().span_context().clone();
+
+    if span_context.is_valid() {
+        span.record("trace_id", span_context.trace_id().to_string());
+    }
+}
@@ -0,0 +1,35 @@
+use opentelemetry_sdk::error::OTelSdkResult;
+use opentelemetry_sdk::trace::{SpanData, SpanExporter};
+use std::io::Write;
+
+/// Prints finished spans to stdout, one line per span. Intended for local runs.
+#[derive(Debug, Default)]
+pub struct StdoutExporter;
+
+impl SpanExporter for StdoutExporter {
+    async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
+        let mut stdout = std::io::stdout().lock();
+
+        for span in batch {
+            let duration = span.end_time.duration_since(span.start_time).unwrap_or_default();
+            let attributes = span
+                .attributes
+                .iter()
+                .map(|attribute| format!("{}={}", attribute.key, attribute.value))
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            // A closed stdout is not worth failing the export for.
+            let _ = writeln!(
+                stdout,
+                "span {} trace_id={} span_id={} parent_span_id={} duration={duration:?} {attributes}",
+                span.name,
+                span.span_context.trace_id(),
+                span.span_context.span_id(),
+                span.parent_span_id,
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,58 @@
+use crate::setting::settings::{LogFormat, Logging, TelemetryConfig, TraceExporter};
+use crate::telemetry::stdout_exporter::StdoutExporter;
+use anyhow::Error;
+use opentelemetry::global;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use std::str::FromStr;
+use tracing_subscriber::filter::{LevelFilter, filter_fn};
+use tracing_subscriber::prelude::*;
+
+/// Installs the global subscriber: logs in the configured format, and spans of the service exported to OpenTelemetry.
+/// Records of the `log` crate (e.g. from `librdkafka`) are forwarded to the subscriber.
+/// The returned provider should be shut down on exit, so buffered spans are exported.
+pub fn init(logging: &Logging, config: &TelemetryConfig) -> Result<SdkTracerProvider, Error> {
+    let log_level = LevelFilter::from_str(logging.log_level.as_str()).unwrap_or(LevelFilter::INFO);
+
+    let resource = Resource::builder()
+        .with_service_name(config.service_name.clone())
+        .build();
+    let mut provider_builder = SdkTracerProvider::builder().with_resource(resource);
+
+    // Without an exporter spans are not exported, but the trace context is still propagated.
+    match config.exporter {
+        TraceExporter::None => {}
+        TraceExporter::Stdout => provider_builder = provider_builder.with_simple_exporter(StdoutExporter),
+        TraceExporter::Otlp => {
+            let exporter = opentelemetry_otlp::SpanExporter::builder()
+                .with_http()
+                .with_endpoint(config.otlp_endpoint.as_str())
+                .build()?;
+            provider_builder = provider_builder.with_batch_exporter(exporter);
+        }
+    }
+
+    let provider = provider_builder.build();
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let fmt_layer = match logging.format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer().json().boxed(),
+    };
+
+    // Only spans of the service are exported, not those of the libraries (e.g. AWS SDK).
+    let otel_layer = tracing_opentelemetry::layer()
+        .with_tracer(provider.tracer(env!("CARGO_PKG_NAME")))
+        .with_filter(filter_fn(|metadata| metadata.target().starts_with(env!("CARGO_CRATE_NAME"))));
+
+    tracing_subscriber::registry()
+        .with(fmt_layer)
+        .with(otel_layer)
+        .with(log_level)
+        .try_init()?;
+
+    Ok(provider)
+}
@@ -0,0 +1,155 @@
+use crate::daemon::outbox_daemon::OutboxDaemon;
+use crate::kafka::memory_producer::MemoryProducer;
+use crate::kafka::message_processor::MessageProcessor;
+use crate::models::input_message::{InputMessage, PredictType};
+use crate::models::output_message::{OutputMessage, TaskStatus};
+use crate::setting::settings::{
+    BackoffConfig, KafkaConfig, KafkaTopics, PublishRetryConfig, RedisConfig, RedisQueues, S3Config, Settings,
+    ShutdownConfig,
+};
+use crate::storage::memory_queue::MemoryQueue;
+use crate::storage::memory_storage::MemoryStorage;
+use crate::storage::memory_task_tracker::MemoryTaskTracker;
+use crate::storage::queue::Queue;
+use rdkafka::Timestamp;
+use rdkafka::message::OwnedMessage;
+use std::sync::Arc;
+
+pub const INPUT_TOPIC: &str = "tasks";
+pub const OUTPUT_TOPIC: &str = "results";
+pub const INBOX: &str = "inbox";
+pub const OUTBOX: &str = "outbox";
+pub const DEAD_LETTER: &str = "dead_letter";
+const POISON: &str = "poison";
+
+/// Settings of the service for tests: short timeouts, no S3 transfer retries,
+/// and a publish retry delayed by a minute at least, so a retried message is not published again within a test.
+pub fn settings() -> Settings {
+    Settings {
+        s3: S3Config {
+            transfer_max_attempts: 1,
+            ..Default::default()
+        },
+        redis: RedisConfig {
+            poll_delay_ms: 10,
+            read_delay_ms: 10,
+            queues: RedisQueues {
+                inbox: INBOX.to_string(),
+                outbox: OUTBOX.to_string(),
+                poison: POISON.to_string(),
+                dead_letter: DEAD_LETTER.to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        kafka: KafkaConfig {
+            batch_timeout_ms: 100,
+            topics: KafkaTopics {
+                input: INPUT_TOPIC.to_string(),
+                output: OUTPUT_TOPIC.to_string(),
+                dlq: None,
+            },
+            publish_retry: PublishRetryConfig {
+                max_attempts: 3,
+                backoff: BackoffConfig {
+                    base_delay_ms: 60_000,
+                    max_delay_ms: 60_000,
+                },
+            },
+            ..Default::default()
+        },
+        shutdown: ShutdownConfig { timeout_ms: 100 },
+        ..Default::default()
+    }
+}
+
+/// Components of the service on in-memory backends, with a producer recording published records instead of Kafka.
+pub struct TestService {
+    pub settings: Arc<Settings>,
+    pub inbox_queue: Arc<dyn Queue<InputMessage>>,
+    pub outbox_queue: Arc<dyn Queue<OutputMessage>>,
+    pub task_tracker: Arc<MemoryTaskTracker>,
+    pub outer_storage: Arc<MemoryStorage>,
+    pub inner_storage: Arc<MemoryStorage>,
+    pub producer: Arc<MemoryProducer>,
+}
+
+impl TestService {
+    /// The producer is transactional if `kafka.producer.transactional_id` is set.
+    pub fn new(settings: Settings) -> Self {
+        let queue = Arc::new(MemoryQueue::new(POISON));
+        let transactional = settings.kafka.producer.transactional_id.is_some();
+
+        TestService {
+            producer: Arc::new(MemoryProducer::new(&settings.kafka.topics.output, transactional)),
+            settings: Arc::new(settings),
+            inbox_queue: queue.clone(),
+            outbox_queue: queue,
+            task_tracker: Arc::new(MemoryTaskTracker::new()),
+            outer_storage: Arc::new(MemoryStorage::new("outer")),
+            inner_storage: Arc::new(MemoryStorage::new("inner")),
+        }
+    }
+
+    pub fn processor(&self) -> MessageProcessor {
+        MessageProcessor::new(
+            self.inbox_queue.clone(),
+            self.outbox_queue.clone(),
+            self.task_tracker.clone(),
+            self.outer_storage.clone(),
+            self.inner_storage.clone(),
+            self.producer.clone(),
+            self.settings.redis.clone(),
+            self.settings.kafka.clone(),
+            self.settings.shutdown.clone(),
+        )
+    }
+
+    pub fn daemon(&self) -> OutboxDaemon {
+        OutboxDaemon::new(
+            self.outbox_queue.clone(),
+            self.task_tracker.clone(),
+            self.outer_storage.clone(),
+            self.inner_storage.clone(),
+            self.settings.clone(),
+            self.producer.clone(),
+        )
+    }
+}
+
+/// Record of the input topic at offset 7 of partition 0.
+pub fn record(payload: Option<&str>) -> OwnedMessage {
+    OwnedMessage::new(
+        payload.map(|payload| payload.as_bytes().to_vec()),
+        None,
+        INPUT_TOPIC.to_string(),
+        Timestamp::NotAvailable,
+        0,
+        7,
+        None,
+    )
+}
+
+pub fn task_payload(id: &str, image_path: &str) -> String {
+    serde_json::json!({
+        "id": id,
+        "image_path": image_path,
+        "result_path": format!("results/{id}.json"),
+        "predict_type": PredictType::TypeOne,
+    })
+    .to_string()
+}
+
+pub fn output_message(id: &str, status: TaskStatus) -> OutputMessage {
+    OutputMessage {
+        id: id.to_string(),
+        status,
+        predict_type: PredictType::TypeOne,
+        result_path: Some(format!("results/{id}.json")),
+        error_code: None,
+        error_message: None,
+        started_at: None,
+        finished_at: None,
+        published_at: None,
+    }
+}